@@ -0,0 +1,287 @@
+use cosmwasm_std::{
+    Addr, Api, CanonicalAddr, Decimal, QuerierWrapper, StdResult, Storage, Timestamp, Uint256,
+};
+use osmosis_std::shim::Timestamp as OsmosisTimestamp;
+use osmosis_std::types::osmosis::twap::v1beta1::TwapQuerier;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+use crate::packet::QuerySupplyOfRequest;
+use crate::state::{find_channel_value_source, ChannelValueSource, FlowType, Path, QuotaPrice};
+use crate::ContractError;
+
+/// The port of the transfer module. Escrow accounts are derived from it
+const TRANSFER_PORT: &str = "transfer";
+
+/// Provides the chain data that the channel value and the price of quotas are
+/// obtained from. Transfers are checked against the chain through
+/// ChainValueProvider, while tests can inject their own values.
+pub trait ChannelValueProvider {
+    /// The total supply of a denom
+    fn supply_of(&self, denom: &str) -> StdResult<Uint256>;
+    /// The amount of a denom held in the escrow account of a transfer channel
+    fn escrow_balance(&self, channel_id: &str, denom: &str) -> StdResult<Uint256>;
+    /// The arithmetic TWAP of `base` in `quote` on a pool since `start`, or
+    /// None if the pool doesn't have one
+    fn twap_price(
+        &self,
+        pool_id: u64,
+        base: &str,
+        quote: &str,
+        start: Timestamp,
+    ) -> StdResult<Option<Decimal>>;
+}
+
+pub struct ChainValueProvider<'a> {
+    querier: QuerierWrapper<'a>,
+    api: &'a dyn Api,
+}
+
+impl<'a> ChainValueProvider<'a> {
+    pub fn new(querier: QuerierWrapper<'a>, api: &'a dyn Api) -> Self {
+        ChainValueProvider { querier, api }
+    }
+}
+
+impl ChannelValueProvider for ChainValueProvider<'_> {
+    // osmosis-std doesn't currently support the SupplyOf query, so it is
+    // defined in the packet module
+    fn supply_of(&self, denom: &str) -> StdResult<Uint256> {
+        let res = QuerySupplyOfRequest {
+            denom: denom.to_string(),
+        }
+        .query(&self.querier)?;
+        Uint256::from_str(&res.amount.unwrap_or_default().amount)
+    }
+
+    fn escrow_balance(&self, channel_id: &str, denom: &str) -> StdResult<Uint256> {
+        let escrow = escrow_address(self.api, channel_id)?;
+        let balance = self.querier.query_balance(escrow, denom)?;
+        Ok(balance.amount.into())
+    }
+
+    // The twap module returns an error when the pool doesn't exist, doesn't
+    // hold the denoms, or has no records for the window
+    fn twap_price(
+        &self,
+        pool_id: u64,
+        base: &str,
+        quote: &str,
+        start: Timestamp,
+    ) -> StdResult<Option<Decimal>> {
+        let start = OsmosisTimestamp {
+            seconds: start.seconds() as i64,
+            nanos: 0,
+        };
+        let twap = TwapQuerier::new(&self.querier).arithmetic_twap_to_now(
+            pool_id,
+            base.to_string(),
+            quote.to_string(),
+            Some(start),
+        );
+        match twap {
+            Ok(twap) => Decimal::from_str(&twap.arithmetic_twap).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Derives the escrow account of a transfer channel the same way ibc-go does:
+/// the first 20 bytes of sha256("ics20-1" + 0x00 + "{port}/{channel}")
+pub fn escrow_address(api: &dyn Api, channel_id: &str) -> StdResult<Addr> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"ics20-1");
+    hasher.update([0]);
+    hasher.update(format!("{TRANSFER_PORT}/{channel_id}").as_bytes());
+    let hash = hasher.finalize();
+    api.addr_humanize(&CanonicalAddr::from(&hash[..20]))
+}
+
+/// Returns the current value of the denom of a path, obtained from the source
+/// configured for the path
+pub fn channel_value(
+    storage: &dyn Storage,
+    provider: &dyn ChannelValueProvider,
+    path: &Path,
+) -> StdResult<Uint256> {
+    let source = find_channel_value_source(storage, path)?;
+    value_from(&source, provider, path)
+}
+
+fn value_from(
+    source: &ChannelValueSource,
+    provider: &dyn ChannelValueProvider,
+    path: &Path,
+) -> StdResult<Uint256> {
+    match source {
+        ChannelValueSource::Supply => provider.supply_of(&path.denom),
+        ChannelValueSource::Escrow => provider.escrow_balance(&path.channel, &path.denom),
+        ChannelValueSource::Fixed { value } => Ok(*value),
+    }
+}
+
+/// Returns the value of the denom of a path before a transfer was processed.
+///
+/// The ibc transfer module burns or escrows the funds of a send before calling
+/// the contract. This undoes those changes so that the channel value that we
+/// track matches the channel value at the moment when the ibc transaction
+/// started executing. Receives are checked before the funds are minted or
+/// released, so they don't need to be adjusted.
+pub fn value_before_transfer(
+    storage: &dyn Storage,
+    provider: &dyn ChannelValueProvider,
+    path: &Path,
+    funds: Uint256,
+    direction: &FlowType,
+) -> StdResult<Uint256> {
+    let source = find_channel_value_source(storage, path)?;
+    let value = value_from(&source, provider, path)?;
+    if *direction == FlowType::In {
+        return Ok(value);
+    }
+    let native = !path.denom.contains("ibc");
+    Ok(match source {
+        // Non-Native tokens get removed from the supply on send. Add that amount back
+        ChannelValueSource::Supply if !native => value.saturating_add(funds),
+        // Native tokens increase the escrow amount on send. Remove that amount here
+        ChannelValueSource::Escrow if native => value.saturating_sub(funds),
+        _ => value,
+    })
+}
+
+/// Returns the price of a denom in the reference denom of a quota. If the pool
+/// has no TWAP, the quota keeps using the last price it saw, and transfers are
+/// rejected if it has never been priced.
+pub fn quota_price(
+    provider: &dyn ChannelValueProvider,
+    price: &QuotaPrice,
+    denom: &str,
+    last_price: Option<Decimal>,
+    now: Timestamp,
+) -> Result<Decimal, ContractError> {
+    if denom == price.reference_denom {
+        return Ok(Decimal::one());
+    }
+    // Windows that go back further than the block time start at the epoch
+    let window = price.window.saturating_mul(1_000_000_000);
+    let start = Timestamp::from_nanos(now.nanos().saturating_sub(window));
+    provider
+        .twap_price(price.pool_id, denom, &price.reference_denom, start)?
+        .or(last_price)
+        .ok_or_else(|| ContractError::PriceUnavailable {
+            denom: denom.to_string(),
+            reference_denom: price.reference_denom.clone(),
+            pool_id: price.pool_id,
+        })
+}
+
+/// Returns the same value for every query. No pool has a TWAP
+#[cfg(test)]
+pub struct MockValueProvider(pub Uint256);
+
+#[cfg(test)]
+impl ChannelValueProvider for MockValueProvider {
+    fn supply_of(&self, _denom: &str) -> StdResult<Uint256> {
+        Ok(self.0)
+    }
+
+    fn escrow_balance(&self, _channel_id: &str, _denom: &str) -> StdResult<Uint256> {
+        Ok(self.0)
+    }
+
+    fn twap_price(
+        &self,
+        _pool_id: u64,
+        _base: &str,
+        _quote: &str,
+        _start: Timestamp,
+    ) -> StdResult<Option<Decimal>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockStorage;
+
+    use super::*;
+    use crate::state::CHANNEL_VALUE_SOURCES;
+
+    #[test] // Tests that the value of a path undoes the effects of sends on its source
+    fn value_before_transfer_by_source() {
+        let mut storage = MockStorage::new();
+        let provider = MockValueProvider(1000_u32.into());
+        let funds = Uint256::from(100_u32);
+        let value = |storage: &dyn Storage, denom: &str, direction: FlowType| {
+            let path = Path::new("channel", denom);
+            value_before_transfer(storage, &provider, &path, funds, &direction).unwrap()
+        };
+
+        // Sends of non-native tokens burn them, reducing the supply
+        assert_eq!(
+            value(&storage, "ibc/ABC", FlowType::Out),
+            Uint256::from(1100_u32)
+        );
+        assert_eq!(
+            value(&storage, "ibc/ABC", FlowType::In),
+            Uint256::from(1000_u32)
+        );
+        assert_eq!(
+            value(&storage, "uosmo", FlowType::Out),
+            Uint256::from(1000_u32)
+        );
+
+        // Sends of native tokens increase the escrow amount
+        let key = (format!("any"), format!("uosmo"));
+        CHANNEL_VALUE_SOURCES
+            .save(&mut storage, key, &ChannelValueSource::Escrow)
+            .unwrap();
+        assert_eq!(
+            value(&storage, "uosmo", FlowType::Out),
+            Uint256::from(900_u32)
+        );
+        assert_eq!(
+            value(&storage, "uosmo", FlowType::In),
+            Uint256::from(1000_u32)
+        );
+
+        // Fixed values are never adjusted. The exact path takes precedence
+        let key = (format!("channel"), format!("uosmo"));
+        let source = ChannelValueSource::Fixed {
+            value: 5000_u32.into(),
+        };
+        CHANNEL_VALUE_SOURCES
+            .save(&mut storage, key, &source)
+            .unwrap();
+        assert_eq!(
+            value(&storage, "uosmo", FlowType::Out),
+            Uint256::from(5000_u32)
+        );
+    }
+
+    #[test] // Tests that prices fall back to the last one, even with windows longer than the chain
+    fn quota_price_fallback() {
+        let provider = MockValueProvider(1000_u32.into());
+        let price = QuotaPrice {
+            reference_denom: format!("uusdc"),
+            pool_id: 1,
+            window: u64::MAX,
+        };
+        let now = Timestamp::from_seconds(10);
+        let last_price = Some(Decimal::percent(150));
+
+        let res = quota_price(&provider, &price, "uusdc", None, now).unwrap();
+        assert_eq!(res, Decimal::one());
+        let res = quota_price(&provider, &price, "uatom", last_price, now).unwrap();
+        assert_eq!(res, Decimal::percent(150));
+        let err = quota_price(&provider, &price, "uatom", None, now).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PriceUnavailable {
+                denom: format!("uatom"),
+                reference_denom: format!("uusdc"),
+                pool_id: 1,
+            }
+        );
+    }
+}
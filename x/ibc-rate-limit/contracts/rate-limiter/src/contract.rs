@@ -1,12 +1,13 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
 use cw2::set_contract_version;
 
+use crate::channel_value::ChainValueProvider;
 use crate::error::ContractError;
 use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, SudoMsg};
-use crate::state::{FlowType, GOVMODULE, IBCMODULE};
-use crate::{execute, query, sudo};
+use crate::state::{FlowType, GOVMODULE, GUARDIAN, IBCMODULE};
+use crate::{execute, migrations, query, sudo};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:rate-limiter";
@@ -22,6 +23,9 @@ pub fn instantiate(
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     IBCMODULE.save(deps.storage, &msg.ibc_module)?;
     GOVMODULE.save(deps.storage, &msg.gov_module)?;
+    if let Some(guardian) = &msg.guardian {
+        GUARDIAN.save(deps.storage, guardian)?;
+    }
 
     execute::add_new_paths(deps, msg.paths, env.block.time)?;
 
@@ -37,70 +41,184 @@ pub fn execute(
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    if execute::requires_timelock(deps.as_ref(), &msg)? {
+        return execute::try_queue_change(deps, info.sender, msg, env.block.time);
+    }
+    execute_msg(deps, env, info.sender, msg)
+}
+
+// Queued changes are executed on behalf of the address that requested them, so
+// the sender is passed separately from the message info
+fn execute_msg(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::AddPath {
             channel_id,
             denom,
             quotas,
-        } => execute::try_add_path(deps, info.sender, channel_id, denom, quotas, env.block.time),
+        } => execute::try_add_path(deps, sender, channel_id, denom, quotas, env.block.time),
         ExecuteMsg::RemovePath { channel_id, denom } => {
-            execute::try_remove_path(deps, info.sender, channel_id, denom)
+            execute::try_remove_path(deps, sender, channel_id, denom)
         }
         ExecuteMsg::ResetPathQuota {
             channel_id,
             denom,
             quota_id,
-        } => execute::try_reset_path_quota(
-            deps,
-            info.sender,
+        } => {
+            execute::try_reset_path_quota(deps, sender, channel_id, denom, quota_id, env.block.time)
+        }
+        ExecuteMsg::AddQuota {
+            channel_id,
+            denom,
+            quota,
+        } => execute::try_add_quota(deps, sender, channel_id, denom, quota, env.block.time),
+        ExecuteMsg::UpdateQuota {
+            channel_id,
+            denom,
+            quota,
+        } => execute::try_update_quota(deps, sender, channel_id, denom, quota, env.block.time),
+        ExecuteMsg::RemoveQuota {
             channel_id,
             denom,
             quota_id,
-            env.block.time,
-        ),
+        } => execute::try_remove_quota(deps, sender, channel_id, denom, quota_id),
+        ExecuteMsg::SetAddressQuotas {
+            channel_id,
+            denom,
+            quotas,
+        } => execute::try_set_address_quotas(deps, sender, channel_id, denom, quotas),
+        ExecuteMsg::AddExemption {
+            address,
+            channel_id,
+            denom,
+            mode,
+        } => execute::try_add_exemption(deps, sender, address, channel_id, denom, mode),
+        ExecuteMsg::RemoveExemption {
+            address,
+            channel_id,
+            denom,
+        } => execute::try_remove_exemption(deps, sender, address, channel_id, denom),
+        ExecuteMsg::Pause { scope } => execute::try_pause(deps, sender, scope, env.block.time),
+        ExecuteMsg::Unpause { scope } => execute::try_unpause(deps, sender, scope),
+        ExecuteMsg::SetGuardian { guardian } => execute::try_set_guardian(deps, sender, guardian),
+        ExecuteMsg::ProposeAdmin { role, address } => {
+            execute::try_propose_admin(deps, sender, role, address)
+        }
+        ExecuteMsg::AcceptAdmin { role } => execute::try_accept_admin(deps, sender, role),
+        ExecuteMsg::SetTimelock { delay } => execute::try_set_timelock(deps, sender, delay),
+        ExecuteMsg::ExecutePendingChange { id } => {
+            let change = execute::take_pending_change(deps.storage, id, env.block.time)?;
+            let response = execute_msg(deps, env, change.proposer, change.msg)?;
+            Ok(response.add_attribute("pending_change_id", id.to_string()))
+        }
+        ExecuteMsg::CancelPendingChange { id } => {
+            execute::try_cancel_pending_change(deps, sender, id)
+        }
+        ExecuteMsg::SetChannelValueSource {
+            channel_id,
+            denom,
+            source,
+        } => execute::try_set_channel_value_source(deps, sender, channel_id, denom, source),
+        ExecuteMsg::SetChannelQuotas { channel_id, quotas } => {
+            execute::try_set_channel_quotas(deps, sender, channel_id, quotas, env.block.time)
+        }
+        ExecuteMsg::SetDenomWeight { denom, weight } => {
+            execute::try_set_denom_weight(deps, sender, denom, weight)
+        }
+        ExecuteMsg::SetVerboseResponses { enabled } => {
+            execute::try_set_verbose_responses(deps, sender, enabled)
+        }
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    let provider = ChainValueProvider::new(deps.querier, deps.api);
     match msg {
-        SudoMsg::SendPacket {
-            packet,
-            #[cfg(test)]
-            channel_value_mock,
-        } => sudo::process_packet(
-            deps,
-            packet,
-            FlowType::Out,
-            env.block.time,
-            #[cfg(test)]
-            channel_value_mock,
-        ),
-        SudoMsg::RecvPacket {
-            packet,
-            #[cfg(test)]
-            channel_value_mock,
-        } => sudo::process_packet(
-            deps,
-            packet,
-            FlowType::In,
-            env.block.time,
-            #[cfg(test)]
-            channel_value_mock,
-        ),
-        SudoMsg::UndoSend { packet } => sudo::undo_send(deps, packet),
+        SudoMsg::SendPacket { packet } => {
+            sudo::process_packet(deps, packet, FlowType::Out, env.block.time, &provider)
+        }
+        SudoMsg::RecvPacket { packet } => {
+            sudo::process_packet(deps, packet, FlowType::In, env.block.time, &provider)
+        }
+        SudoMsg::UndoSend { packet } => sudo::undo_send(deps, packet, env.block.time),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetQuotas { channel_id, denom } => query::get_quotas(deps, channel_id, denom),
+        QueryMsg::ListPaths { start_after, limit } => query::list_paths(deps, start_after, limit),
+        QueryMsg::ListPathsForChannel {
+            channel_id,
+            start_after,
+            limit,
+        } => query::list_paths_for_channel(deps, channel_id, start_after, limit),
+        QueryMsg::ListPathsForDenom {
+            denom,
+            start_after,
+            limit,
+        } => query::list_paths_for_denom(deps, denom, start_after, limit),
+        QueryMsg::GetRemainingCapacity { channel_id, denom } => {
+            query::get_remaining_capacity(deps, channel_id, denom, env.block.time)
+        }
+        QueryMsg::GetAddressQuotas {
+            channel_id,
+            denom,
+            address,
+        } => query::get_address_quotas(deps, channel_id, denom, address, env.block.time),
+        QueryMsg::SimulateTransfer {
+            channel_id,
+            denom,
+            amount,
+            direction,
+            address,
+        } => query::simulate_transfer(
+            deps,
+            channel_id,
+            denom,
+            amount,
+            direction,
+            address,
+            env.block.time,
+            &ChainValueProvider::new(deps.querier, deps.api),
+        ),
+        QueryMsg::GetConfig {} => query::get_config(deps),
+        QueryMsg::GetChannelValueSource { channel_id, denom } => {
+            query::get_channel_value_source(deps, channel_id, denom)
+        }
+        QueryMsg::GetPendingChanges { start_after, limit } => {
+            query::get_pending_changes(deps, start_after, limit)
+        }
+        QueryMsg::IsPaused { channel_id, denom } => query::is_paused(deps, channel_id, denom),
+        QueryMsg::GetExemption {
+            address,
+            channel_id,
+            denom,
+        } => query::get_exemption(deps, address, channel_id, denom),
+        QueryMsg::GetChannelQuotas { channel_id } => query::get_channel_quotas(deps, channel_id),
+        QueryMsg::GetDenomWeight { denom } => query::get_denom_weight(deps, denom),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    unimplemented!()
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    match msg {
+        MigrateMsg::Upgrade {
+            gov_module,
+            ibc_module,
+        } => migrations::upgrade(
+            deps,
+            CONTRACT_NAME,
+            CONTRACT_VERSION,
+            gov_module,
+            ibc_module,
+        ),
+    }
 }
@@ -2,17 +2,32 @@
 
 use crate::packet::Packet;
 use crate::{contract::*, test_msg_recv, test_msg_send, ContractError};
-use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{from_binary, Addr, Attribute, Uint256};
+use cosmwasm_std::testing::{mock_env, mock_info};
+use cosmwasm_std::{from_binary, Addr, Attribute, Decimal, DepsMut, OwnedDeps, Uint256};
+use std::str::FromStr;
 
+use crate::helpers::mock_dependencies;
 use crate::helpers::tests::verify_query_response;
-use crate::msg::{InstantiateMsg, PathMsg, QueryMsg, QuotaMsg, SudoMsg};
-use crate::state::tests::RESET_TIME_WEEKLY;
-use crate::state::{RateLimit, GOVMODULE, IBCMODULE, RATE_LIMIT_TRACKERS};
+use crate::msg::{
+    AdminRole, Config, ExecuteMsg, InstantiateMsg, PathMsg, PathRateLimits, PauseScope, Percentage,
+    QueryMsg, QuotaMsg, QuotaUsage, RemainingCapacity, SimulateTransferResponse, SudoMsg,
+};
+use crate::state::tests::{RESET_TIME_DAILY, RESET_TIME_WEEKLY};
+use crate::state::{
+    ChannelValueSource, ExemptionMode, FlowType, QuotaKind, RateLimit, ADDRESS_RATE_LIMIT_TRACKERS,
+    GOVMODULE, IBCMODULE, MAX_PRICE_WINDOW, MAX_QUOTA_DURATION, MAX_SLIDING_WINDOW_BUCKETS,
+    RATE_LIMIT_TRACKERS, SENT_PACKETS,
+};
 
 const IBC_ADDR: &str = "IBC_MODULE";
 const GOV_ADDR: &str = "GOV_MODULE";
 
+// Adds the usage of every quota to the responses of transfers
+fn enable_verbose_responses(deps: DepsMut) {
+    let msg = ExecuteMsg::SetVerboseResponses { enabled: true };
+    execute(deps, mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+}
+
 #[test] // Tests we ccan instantiate the contract and that the owners are set correctly
 fn proper_instantiation() {
     let mut deps = mock_dependencies();
@@ -20,6 +35,7 @@ fn proper_instantiation() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![],
     };
     let info = mock_info(IBC_ADDR, &vec![]);
@@ -41,6 +57,7 @@ fn consume_allowance() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![PathMsg {
             channel_id: format!("any"),
             denom: format!("denom"),
@@ -49,11 +66,12 @@ fn consume_allowance() {
     };
     let info = mock_info(GOV_ADDR, &vec![]);
     let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
 
+    deps.querier.set_supply("denom", 3_300_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom") ,
-        channel_value: 3_300_u32.into(),
         funds: 300_u32.into()
     );
     let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
@@ -65,7 +83,6 @@ fn consume_allowance() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_300_u32.into(),
         funds: 300_u32.into()
     );
     let err = sudo(deps.as_mut(), mock_env(), msg).unwrap_err();
@@ -80,6 +97,7 @@ fn symetric_flows_dont_consume_allowance() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![PathMsg {
             channel_id: format!("any"),
             denom: format!("denom"),
@@ -88,17 +106,18 @@ fn symetric_flows_dont_consume_allowance() {
     };
     let info = mock_info(GOV_ADDR, &vec![]);
     let _res = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
 
+    deps.querier.set_supply("denom", 3_300_u32);
     let send_msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_300_u32.into(),
         funds: 300_u32.into()
     );
+    deps.querier.set_supply("denom", 3_000_u32);
     let recv_msg = test_msg_recv!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 300_u32.into()
     );
 
@@ -142,6 +161,7 @@ fn asymetric_quotas() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![PathMsg {
             channel_id: format!("any"),
             denom: format!("denom"),
@@ -150,12 +170,13 @@ fn asymetric_quotas() {
     };
     let info = mock_info(GOV_ADDR, &vec![]);
     let _res = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
 
     // Sending 2%
+    deps.querier.set_supply("denom", 3_060_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_060_u32.into(),
         funds: 60_u32.into()
     );
     let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
@@ -167,7 +188,6 @@ fn asymetric_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_060_u32.into(),
         funds: 60_u32.into()
     );
 
@@ -177,10 +197,10 @@ fn asymetric_quotas() {
     assert_eq!(value, "120");
 
     // Receiving 1% should still work. 4% *sent* through the path, but we can still receive.
+    deps.querier.set_supply("denom", 3_000_u32);
     let recv_msg = test_msg_recv!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 30_u32.into()
     );
     let res = sudo(deps.as_mut(), mock_env(), recv_msg).unwrap();
@@ -192,10 +212,10 @@ fn asymetric_quotas() {
     assert_eq!(value, "90");
 
     // Sending 2%. Should fail. In balance, we've sent 4% and received 1%, so only 1% left to send.
+    deps.querier.set_supply("denom", 3_060_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_060_u32.into(),
         funds: 60_u32.into()
     );
     let err = sudo(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
@@ -205,7 +225,6 @@ fn asymetric_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_060_u32.into(),
         funds: 30_u32.into()
     );
     let res = sudo(deps.as_mut(), mock_env(), msg.clone()).unwrap();
@@ -225,6 +244,7 @@ fn query_state() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![PathMsg {
             channel_id: format!("any"),
             denom: format!("denom"),
@@ -243,8 +263,14 @@ fn query_state() {
     let res = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
     let value: Vec<RateLimit> = from_binary(&res).unwrap();
     assert_eq!(value[0].quota.name, "weekly");
-    assert_eq!(value[0].quota.max_percentage_send, 10);
-    assert_eq!(value[0].quota.max_percentage_recv, 10);
+    assert_eq!(
+        value[0].quota.max_percentage_send,
+        Some(Decimal::percent(1000))
+    );
+    assert_eq!(
+        value[0].quota.max_percentage_recv,
+        Some(Decimal::percent(1000))
+    );
     assert_eq!(value[0].quota.duration, RESET_TIME_WEEKLY);
     assert_eq!(value[0].flow.inflow, Uint256::from(0_u32));
     assert_eq!(value[0].flow.outflow, Uint256::from(0_u32));
@@ -253,18 +279,18 @@ fn query_state() {
         env.block.time.plus_seconds(RESET_TIME_WEEKLY)
     );
 
+    deps.querier.set_supply("denom", 3_300_u32);
     let send_msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_300_u32.into(),
         funds: 300_u32.into()
     );
     sudo(deps.as_mut(), mock_env(), send_msg.clone()).unwrap();
 
+    deps.querier.set_supply("denom", 3_000_u32);
     let recv_msg = test_msg_recv!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 30_u32.into()
     );
     sudo(deps.as_mut(), mock_env(), recv_msg.clone()).unwrap();
@@ -283,34 +309,133 @@ fn query_state() {
     );
 }
 
-#[test] // Tests quota percentages are between [0,100]
+#[test] // Tests that invalid quota configurations are rejected
 fn bad_quotas() {
-    let mut deps = mock_dependencies();
-
-    let msg = InstantiateMsg {
-        gov_module: Addr::unchecked(GOV_ADDR),
-        ibc_module: Addr::unchecked(IBC_ADDR),
-        paths: vec![PathMsg {
-            channel_id: format!("any"),
-            denom: format!("denom"),
-            quotas: vec![QuotaMsg {
-                name: "bad_quota".to_string(),
-                duration: 200,
-                send_recv: (5000, 101),
+    let instantiate_with = |quotas: Vec<QuotaMsg>| {
+        let msg = InstantiateMsg {
+            gov_module: Addr::unchecked(GOV_ADDR),
+            ibc_module: Addr::unchecked(IBC_ADDR),
+            guardian: None,
+            paths: vec![PathMsg {
+                channel_id: format!("any"),
+                denom: format!("denom"),
+                quotas,
             }],
-        }],
+        };
+        let info = mock_info(IBC_ADDR, &vec![]);
+        let mut deps = mock_dependencies();
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg);
+        (deps, res)
     };
-    let info = mock_info(IBC_ADDR, &vec![]);
 
-    let env = mock_env();
-    instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+    // Quotas can't be higher than 100%
+    let err = instantiate_with(vec![QuotaMsg::new("bad_quota", 200, 5000, 101)])
+        .1
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::InvalidQuotaPercentage {
+            quota_id: format!("bad_quota"),
+            percentage: Decimal::percent(500_000),
+        }
+    );
+    let err = instantiate_with(vec![QuotaMsg::new("bad_quota", 200, 100, 101)])
+        .1
+        .unwrap_err();
+    assert!(matches!(err, ContractError::InvalidQuotaPercentage { .. }));
+
+    let err = instantiate_with(vec![QuotaMsg::new("bad_quota", 0, 10, 10)])
+        .1
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::ZeroQuotaDuration {
+            quota_id: format!("bad_quota")
+        }
+    );
+
+    let err = instantiate_with(vec![QuotaMsg::new("", 200, 10, 10)])
+        .1
+        .unwrap_err();
+    assert_eq!(err, ContractError::EmptyQuotaName {});
+
+    // Durations that don't fit in a timestamp
+    let err = instantiate_with(vec![QuotaMsg::new("bad_quota", u64::MAX, 10, 10)])
+        .1
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::QuotaDurationTooLong {
+            quota_id: format!("bad_quota"),
+            max: MAX_QUOTA_DURATION,
+        }
+    );
+    let (_, res) = instantiate_with(vec![QuotaMsg::new("ok", MAX_QUOTA_DURATION, 10, 10)]);
+    res.unwrap();
+
+    // Quotas must limit something
+    let mut quota = QuotaMsg::new("bad_quota", 200, 10, 10);
+    quota.send_recv = None;
+    let err = instantiate_with(vec![quota]).1.unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::MissingQuotaLimits {
+            quota_id: format!("bad_quota")
+        }
+    );
+
+    let quota =
+        QuotaMsg::new("bad_quota", 200, 10, 10).with_kind(QuotaKind::SlidingWindow { buckets: 0 });
+    let err = instantiate_with(vec![quota]).1.unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::ZeroQuotaBuckets {
+            quota_id: format!("bad_quota")
+        }
+    );
 
-    // If a quota is higher than 100%, we set it to 100%
+    // Sliding windows can't have more buckets than the limit or than seconds
+    let buckets = MAX_SLIDING_WINDOW_BUCKETS + 1;
+    let quota = QuotaMsg::new("bad_quota", RESET_TIME_WEEKLY, 10, 10)
+        .with_kind(QuotaKind::SlidingWindow { buckets });
+    let err = instantiate_with(vec![quota]).1.unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::TooManyQuotaBuckets {
+            quota_id: format!("bad_quota"),
+            max: MAX_SLIDING_WINDOW_BUCKETS.into(),
+        }
+    );
+    let quota =
+        QuotaMsg::new("bad_quota", 10, 10, 10).with_kind(QuotaKind::SlidingWindow { buckets: 11 });
+    let err = instantiate_with(vec![quota]).1.unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::TooManyQuotaBuckets {
+            quota_id: format!("bad_quota"),
+            max: 10,
+        }
+    );
+
+    let err = instantiate_with(vec![
+        QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10),
+        QuotaMsg::new("weekly", RESET_TIME_WEEKLY * 2, 20, 20),
+    ])
+    .1
+    .unwrap_err();
+    assert!(matches!(err, ContractError::DuplicateQuotaName { .. }));
+
+    let err = instantiate_with(vec![]).1.unwrap_err();
+    assert!(matches!(err, ContractError::EmptyQuotaList { .. }));
+
+    // 100% is still allowed
+    let (deps, res) = instantiate_with(vec![QuotaMsg::new("bad_quota", 200, 100, 100)]);
+    res.unwrap();
     let query_msg = QueryMsg::GetQuotas {
         channel_id: format!("any"),
         denom: format!("denom"),
     };
-    let res = query(deps.as_ref(), env.clone(), query_msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
     let value: Vec<RateLimit> = from_binary(&res).unwrap();
     verify_query_response(
         &value[0],
@@ -319,8 +444,152 @@ fn bad_quotas() {
         200,
         0_u32.into(),
         0_u32.into(),
-        env.block.time.plus_seconds(200),
+        mock_env().block.time.plus_seconds(200),
+    );
+}
+
+#[test] // Tests that absolute quotas don't depend on the channel value and that the tighter limit applies
+fn absolute_quotas() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![
+            PathMsg::new(
+                "any",
+                "new_denom",
+                vec![QuotaMsg::absolute(
+                    "weekly",
+                    RESET_TIME_WEEKLY,
+                    100_u32,
+                    500_u32,
+                )],
+            ),
+            PathMsg::new(
+                "any",
+                "denom",
+                vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)
+                    .with_amounts(200_u32, 1_000_u32)],
+            ),
+        ],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
+
+    // The quotas are reported with their absolute amounts
+    let query_msg = QueryMsg::GetQuotas {
+        channel_id: format!("any"),
+        denom: format!("new_denom"),
+    };
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    let value: Vec<RateLimit> = from_binary(&res).unwrap();
+    assert_eq!(value[0].quota.max_percentage_send, None);
+    assert_eq!(value[0].quota.max_percentage_recv, None);
+    assert_eq!(value[0].quota.max_amount_send, Some(100_u32.into()));
+    assert_eq!(value[0].quota.max_amount_recv, Some(500_u32.into()));
+
+    // A denom that doesn't exist on this chain yet has no supply, but can be received up to the absolute amount
+    let recv_msg = test_msg_recv!(
+        channel_id: format!("channel"),
+        denom: format!("new_denom"),
+        funds: 300_u32.into()
+    );
+    let res = sudo(deps.as_mut(), mock_env(), recv_msg.clone()).unwrap();
+    let Attribute { key, value } = &res.attributes[5];
+    assert_eq!(key, "weekly_max_in");
+    assert_eq!(value, "500");
+    let err = sudo(deps.as_mut(), mock_env(), recv_msg).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // 10% of 3000 is 300, but only 200 can be sent
+    deps.querier.set_supply("denom", 3_000_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 150_u32.into()
+    );
+    let res = sudo(deps.as_mut(), mock_env(), send_msg.clone()).unwrap();
+    let Attribute { key, value } = &res.attributes[6];
+    assert_eq!(key, "weekly_max_out");
+    assert_eq!(value, "200");
+    let err = sudo(deps.as_mut(), mock_env(), send_msg).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // 10% of 3000 is tighter than the absolute 1000 on receives
+    let recv_msg = test_msg_recv!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 450_u32.into()
+    );
+    let res = sudo(deps.as_mut(), mock_env(), recv_msg).unwrap();
+    let Attribute { key, value } = &res.attributes[5];
+    assert_eq!(key, "weekly_max_in");
+    assert_eq!(value, "300");
+}
+
+#[test] // Tests that quotas can be smaller than 1% and that capacities are rounded down
+fn decimal_quotas() {
+    let mut deps = mock_dependencies();
+
+    // Whole and decimal percentages can be mixed
+    let quota: QuotaMsg =
+        serde_json_wasm::from_str(r#"{"name":"weekly","duration":604800,"send_recv":["0.25",1]}"#)
+            .unwrap();
+    assert_eq!(
+        quota.send_recv,
+        Some((
+            Percentage::Decimal(Decimal::from_str("0.25").unwrap()),
+            Percentage::Whole(1)
+        ))
+    );
+
+    // Negative numbers, fractional numbers, non-decimal strings and other values aren't percentages
+    for send_recv in [
+        r#"[-1,1]"#,
+        r#"[0.25,1]"#,
+        r#"["abc",1]"#,
+        r#"[true,1]"#,
+        r#"[null,1]"#,
+    ] {
+        let json = format!(r#"{{"name":"weekly","duration":604800,"send_recv":{send_recv}}}"#);
+        serde_json_wasm::from_str::<QuotaMsg>(&json).unwrap_err();
+    }
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("any", "denom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
+
+    // 0.25% of 1_999 is 4.9975, which is rounded down
+    deps.querier.set_supply("denom", 1_999_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 4_u32.into()
+    );
+    let res = sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+    let Attribute { key, value } = &res.attributes[5];
+    assert_eq!(key, "weekly_max_in");
+    assert_eq!(value, "19");
+    let Attribute { key, value } = &res.attributes[6];
+    assert_eq!(key, "weekly_max_out");
+    assert_eq!(value, "4");
+
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 1_u32.into()
     );
+    let err = sudo(deps.as_mut(), mock_env(), send_msg).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
 }
 
 #[test] // Tests that undo reverts a packet send without affecting expiration or channel value
@@ -331,6 +600,7 @@ fn undo_send() {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths: vec![PathMsg {
             channel_id: format!("any"),
             denom: format!("denom"),
@@ -340,10 +610,10 @@ fn undo_send() {
     let info = mock_info(GOV_ADDR, &vec![]);
     let _res = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
+    deps.querier.set_supply("denom", 3_300_u32);
     let send_msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_300_u32.into(),
         funds: 300_u32.into()
     );
     let undo_msg = SudoMsg::UndoSend {
@@ -377,6 +647,135 @@ fn undo_send() {
     assert_eq!(trackers.first().unwrap().quota.channel_value, channel_value);
 }
 
+#[test] // Tests that undoing a send only credits the period in which it was sent
+fn undo_send_after_period_ends() {
+    let mut deps = mock_dependencies();
+
+    let quota = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10);
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("any", "denom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 3_000_u32);
+    let packet = |sequence: u64, funds: u32| {
+        Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            funds.into(),
+        )
+        .with_sequence(sequence)
+    };
+    let send = |sequence: u64, funds: u32| SudoMsg::SendPacket {
+        packet: packet(sequence, funds),
+    };
+    let undo = |sequence: u64, funds: u32| SudoMsg::UndoSend {
+        packet: packet(sequence, funds),
+    };
+    let is_recorded = |deps: &OwnedDeps<_, _, _>, sequence: u64| {
+        SENT_PACKETS.has(deps.as_ref().storage, (format!("channel"), sequence))
+    };
+
+    sudo(deps.as_mut(), mock_env(), send(1, 300)).unwrap();
+    assert!(is_recorded(&deps, 1));
+
+    // The period ends and the next one is used up
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(RESET_TIME_WEEKLY + 1);
+    sudo(deps.as_mut(), env.clone(), send(2, 300)).unwrap();
+
+    // The first send can't be reverted from the new period
+    sudo(deps.as_mut(), env.clone(), undo(1, 300)).unwrap();
+    let err = sudo(deps.as_mut(), env.clone(), send(3, 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // Sends of the current period are still reverted. Each send is only undone once
+    let res = sudo(deps.as_mut(), env.clone(), undo(2, 300)).unwrap();
+    assert!(res
+        .attributes
+        .contains(&Attribute::new("any_channel", "true")));
+    assert!(!is_recorded(&deps, 2));
+    sudo(deps.as_mut(), env.clone(), undo(2, 300)).unwrap();
+    sudo(deps.as_mut(), env.clone(), send(4, 300)).unwrap();
+    let err = sudo(deps.as_mut(), env.clone(), send(5, 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // The records of sends that were never undone are removed once they expire
+    env.block.time = env.block.time.plus_seconds(RESET_TIME_WEEKLY + 1);
+    sudo(deps.as_mut(), env.clone(), send(6, 1)).unwrap();
+    assert!(!is_recorded(&deps, 4));
+    assert!(is_recorded(&deps, 6));
+}
+
+#[test] // Tests that updating a quota doesn't stop the sends of the current period from being undone
+fn undo_send_after_quota_update() {
+    let mut deps = mock_dependencies();
+
+    let quota = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10);
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("channel", "denom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 3_000_u32);
+    let packet = |sequence: u64, funds: u32| {
+        Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            funds.into(),
+        )
+        .with_sequence(sequence)
+    };
+    sudo(
+        deps.as_mut(),
+        mock_env(),
+        SudoMsg::SendPacket {
+            packet: packet(1, 300),
+        },
+    )
+    .unwrap();
+
+    // Making the period longer moves its end
+    let msg = ExecuteMsg::UpdateQuota {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        quota: QuotaMsg::new("weekly", RESET_TIME_WEEKLY * 2, 10, 10),
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+    // The send is still reverted from the period that counted it
+    sudo(
+        deps.as_mut(),
+        mock_env(),
+        SudoMsg::UndoSend {
+            packet: packet(1, 300),
+        },
+    )
+    .unwrap();
+    let trackers = RATE_LIMIT_TRACKERS
+        .load(&deps.storage, ("channel".to_string(), "denom".to_string()))
+        .unwrap();
+    assert_eq!(trackers[0].flow.outflow, Uint256::zero());
+    sudo(
+        deps.as_mut(),
+        mock_env(),
+        SudoMsg::SendPacket {
+            packet: packet(2, 300),
+        },
+    )
+    .unwrap();
+}
+
 #[test]
 fn test_basic_message() {
     let json = r#"{"send_packet":{"packet":{"sequence":2,"source_port":"transfer","source_channel":"channel-0","destination_port":"transfer","destination_channel":"channel-0","data":{"denom":"stake","amount":"125000000000011250","sender":"osmo1dwtagd6xzl4eutwtyv6mewra627lkg3n3w26h6","receiver":"osmo1yvjkt8lnpxucjmspaj5ss4aa8562gx0a3rks8s"},"timeout_height":{"revision_height":100}}}}"#;
@@ -397,3 +796,1172 @@ fn test_tokenfactory_message() {
     let _parsed: SudoMsg = serde_json_wasm::from_str(json).unwrap();
     //println!("{parsed:?}");
 }
+
+#[test] // Tests that a single address can't use up the capacity of the whole path
+fn address_quotas() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new(
+            "channel",
+            "denom",
+            vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        )],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let msg = ExecuteMsg::SetAddressQuotas {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        quotas: vec![QuotaMsg::new("weekly_address", RESET_TIME_WEEKLY, 5, 5)],
+    };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+    deps.querier.set_supply("denom", 1_000_u32);
+    // Sends are identified by their sequence when they are undone
+    let packet = |sequence: u64, sender: &str, funds: u32| {
+        Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            funds.into(),
+        )
+        .with_addresses(sender, "receiver")
+        .with_sequence(sequence)
+    };
+    let send = |sequence: u64, sender: &str, funds: u32| SudoMsg::SendPacket {
+        packet: packet(sequence, sender, funds),
+    };
+
+    // Each address can send 5% of the channel value
+    let res = sudo(deps.as_mut(), mock_env(), send(1, "alice", 50)).unwrap();
+    assert!(res.attributes.contains(&Attribute::new("address", "alice")));
+    let err = sudo(deps.as_mut(), mock_env(), send(2, "alice", 1)).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::AddressRateLimitExceded { ref address, .. } if address == "alice"
+    ));
+    sudo(deps.as_mut(), mock_env(), send(3, "bob", 50)).unwrap();
+
+    // The path quota still applies to the sum of all addresses
+    let err = sudo(deps.as_mut(), mock_env(), send(4, "carol", 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    let is_tracked = |deps: &OwnedDeps<_, _, _>, address: &str| {
+        let key = (format!("channel"), format!("denom"), address.to_string());
+        ADDRESS_RATE_LIMIT_TRACKERS.has(deps.as_ref().storage, key)
+    };
+    assert!(is_tracked(&deps, "alice"));
+    assert!(is_tracked(&deps, "bob"));
+
+    // Undoing a send also frees the capacity of the sender. Addresses whose
+    // flow goes back to zero stop being tracked
+    let undo_msg = SudoMsg::UndoSend {
+        packet: packet(1, "alice", 50),
+    };
+    sudo(deps.as_mut(), mock_env(), undo_msg).unwrap();
+    assert!(!is_tracked(&deps, "alice"));
+    sudo(deps.as_mut(), mock_env(), send(5, "alice", 50)).unwrap();
+    assert!(is_tracked(&deps, "alice"));
+
+    // Transfers that don't count towards any quota aren't tracked either
+    sudo(deps.as_mut(), mock_env(), send(8, "dave", 0)).unwrap();
+    assert!(!is_tracked(&deps, "dave"));
+
+    // Receives are tracked for the receiver. Like path quotas, they are netted
+    // against the address' sends
+    let recv_msg = SudoMsg::RecvPacket {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("transfer/channel/denom"),
+            110_u32.into(),
+        )
+        .with_addresses("someone", "alice"),
+    };
+    let err = sudo(deps.as_mut(), mock_env(), recv_msg).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::AddressRateLimitExceded { ref address, .. } if address == "alice"
+    ));
+
+    let query_msg = QueryMsg::GetAddressQuotas {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        address: format!("alice"),
+    };
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    let value: Vec<RateLimit> = from_binary(&res).unwrap();
+    assert_eq!(value[0].quota.name, "weekly_address");
+    assert_eq!(value[0].flow.outflow, Uint256::from(50_u32));
+    assert_eq!(value[0].flow.inflow, Uint256::from(0_u32));
+
+    // Removing the address quotas lets a single address use the whole path again
+    let msg = ExecuteMsg::SetAddressQuotas {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        quotas: vec![],
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    assert!(!is_tracked(&deps, "alice"));
+    assert!(!is_tracked(&deps, "bob"));
+    sudo(deps.as_mut(), mock_env(), send(6, "carol", 0)).unwrap();
+    let undo_msg = SudoMsg::UndoSend {
+        packet: packet(3, "bob", 50),
+    };
+    sudo(deps.as_mut(), mock_env(), undo_msg).unwrap();
+    sudo(deps.as_mut(), mock_env(), send(7, "alice", 50)).unwrap();
+}
+
+#[test] // Tests that exempt addresses are either skipped or only recorded
+fn exemptions() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new(
+            "channel",
+            "denom",
+            vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        )],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
+
+    // Only governance can manage exemptions
+    let msg = ExecuteMsg::AddExemption {
+        address: format!("treasury"),
+        channel_id: None,
+        denom: None,
+        mode: ExemptionMode::Skip,
+    };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(IBC_ADDR, &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let msg = ExecuteMsg::AddExemption {
+        address: format!("market_maker"),
+        channel_id: Some(format!("channel")),
+        denom: Some(format!("denom")),
+        mode: ExemptionMode::Record,
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+    deps.querier.set_supply("denom", 1_000_u32);
+    let send = |sender: &str, funds: u32| SudoMsg::SendPacket {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            funds.into(),
+        )
+        .with_addresses(sender, "receiver"),
+    };
+
+    // Skipped transfers don't count towards the quota
+    let res = sudo(deps.as_mut(), mock_env(), send("treasury", 500)).unwrap();
+    assert!(res
+        .attributes
+        .contains(&Attribute::new("exemption", "skip")));
+    sudo(deps.as_mut(), mock_env(), send("alice", 100)).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send("alice", 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // Recorded transfers are allowed, but count towards the quota
+    let res = sudo(deps.as_mut(), mock_env(), send("market_maker", 200)).unwrap();
+    assert!(res
+        .attributes
+        .contains(&Attribute::new("exemption", "record")));
+    let Attribute { key, value } = &res.attributes[5];
+    assert_eq!(key, "weekly_used_out");
+    assert_eq!(value, "300");
+
+    let query_msg = QueryMsg::GetExemption {
+        address: format!("market_maker"),
+        channel_id: format!("channel"),
+        denom: format!("other_denom"),
+    };
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    let value: Option<ExemptionMode> = from_binary(&res).unwrap();
+    assert_eq!(value, None);
+
+    // Once the exemption is removed, the address is rate limited again
+    let msg = ExecuteMsg::RemoveExemption {
+        address: format!("treasury"),
+        channel_id: None,
+        denom: None,
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send("treasury", 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+}
+
+#[test] // Tests that the guardian can halt traffic
+fn pause_and_unpause() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: Some(Addr::unchecked("guardian")),
+        paths: vec![PathMsg::new(
+            "channel",
+            "denom",
+            vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        )],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 1_000_u32);
+    let send_msg = |channel: &str| {
+        test_msg_send!(
+            channel_id: channel.to_string(),
+            denom: format!("denom"),
+            funds: 10_u32.into()
+        )
+    };
+
+    let pause_channel = ExecuteMsg::Pause {
+        scope: PauseScope::Channel {
+            channel_id: format!("channel"),
+        },
+    };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(IBC_ADDR, &[]),
+        pause_channel.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("guardian", &[]),
+        pause_channel.clone(),
+    )
+    .unwrap();
+
+    // Paused traffic is rejected even if there are no quotas for the path
+    let err = sudo(deps.as_mut(), mock_env(), send_msg("channel")).unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::Paused {
+            channel: format!("channel"),
+            denom: format!("denom")
+        }
+    );
+    deps.querier.set_supply("other_denom", 1_000_u32);
+    let recv_msg = test_msg_recv!(
+        channel_id: format!("channel"),
+        denom: format!("other_denom"),
+        funds: 10_u32.into()
+    );
+    let err = sudo(deps.as_mut(), mock_env(), recv_msg).unwrap_err();
+    assert!(matches!(err, ContractError::Paused { .. }));
+    sudo(deps.as_mut(), mock_env(), send_msg("other_channel")).unwrap();
+
+    // Failed sends can still be reverted
+    let undo_msg = SudoMsg::UndoSend {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            10_u32.into(),
+        ),
+    };
+    sudo(deps.as_mut(), mock_env(), undo_msg).unwrap();
+
+    let query_msg = QueryMsg::IsPaused {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+    };
+    let res = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
+    assert!(from_binary::<bool>(&res).unwrap());
+
+    let msg = ExecuteMsg::Unpause {
+        scope: PauseScope::Channel {
+            channel_id: format!("channel"),
+        },
+    };
+    execute(deps.as_mut(), mock_env(), mock_info("guardian", &[]), msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    assert!(!from_binary::<bool>(&res).unwrap());
+    sudo(deps.as_mut(), mock_env(), send_msg("channel")).unwrap();
+
+    // Governance can also pause, and replace the guardian
+    let msg = ExecuteMsg::Pause {
+        scope: PauseScope::All {},
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send_msg("other_channel")).unwrap_err();
+    assert!(matches!(err, ContractError::Paused { .. }));
+
+    let msg = ExecuteMsg::SetGuardian { guardian: None };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("guardian", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("guardian", &[]),
+        pause_channel,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+}
+
+#[test] // Tests that transfers can be simulated without modifying the state
+fn simulate_transfer() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new(
+            "channel",
+            "denom",
+            vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        )],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 1_000_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 60_u32.into()
+    );
+    sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+
+    let simulate = |amount: u32| QueryMsg::SimulateTransfer {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        amount: amount.into(),
+        direction: FlowType::Out,
+        address: None,
+    };
+
+    let res = query(deps.as_ref(), mock_env(), simulate(30)).unwrap();
+    let value: SimulateTransferResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        value,
+        SimulateTransferResponse::Allowed {
+            exemption: None,
+            quotas: vec![QuotaUsage {
+                channel_id: format!("channel"),
+                denom: format!("denom"),
+                address: None,
+                name: format!("weekly"),
+                used: 90_u32.into(),
+                max: 100_u32.into(),
+                remaining: 10_u32.into(),
+                reset: mock_env().block.time.plus_seconds(RESET_TIME_WEEKLY),
+            }],
+        }
+    );
+
+    let res = query(deps.as_ref(), mock_env(), simulate(50)).unwrap();
+    let value: SimulateTransferResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        value,
+        SimulateTransferResponse::RateLimitExceeded {
+            address: None,
+            channel: format!("channel"),
+            denom: format!("denom"),
+            amount: 50_u32.into(),
+            quota_name: format!("weekly"),
+            used: 60_u32.into(),
+            max: 100_u32.into(),
+            reset: mock_env().block.time.plus_seconds(RESET_TIME_WEEKLY),
+        }
+    );
+
+    // Receives are netted against the sends, so they have more room left
+    let msg = QueryMsg::SimulateTransfer {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        amount: 30_u32.into(),
+        direction: FlowType::In,
+        address: None,
+    };
+    let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+    let value: SimulateTransferResponse = from_binary(&res).unwrap();
+    assert!(matches!(
+        value,
+        SimulateTransferResponse::Allowed { ref quotas, .. }
+            if quotas[0].used.is_zero() && quotas[0].remaining == Uint256::from(130_u32)
+    ));
+
+    // Simulating doesn't modify the flow
+    let query_msg = QueryMsg::GetQuotas {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+    };
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    let value: Vec<RateLimit> = from_binary(&res).unwrap();
+    assert_eq!(value[0].flow.outflow, Uint256::from(60_u32));
+
+    let msg = ExecuteMsg::Pause {
+        scope: PauseScope::All {},
+    };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), simulate(30)).unwrap();
+    let value: SimulateTransferResponse = from_binary(&res).unwrap();
+    assert!(matches!(value, SimulateTransferResponse::Paused { .. }));
+}
+
+#[test] // Tests that the remaining capacity takes netting and expired periods into account
+fn remaining_capacity() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![
+            PathMsg::new(
+                "channel",
+                "denom",
+                vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+            ),
+            PathMsg::new(
+                "any",
+                "denom",
+                vec![QuotaMsg::new("daily", RESET_TIME_DAILY, 5, 5)],
+            ),
+        ],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 1_000_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 40_u32.into()
+    );
+    sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+    let recv_msg = test_msg_recv!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 10_u32.into()
+    );
+    sudo(deps.as_mut(), mock_env(), recv_msg).unwrap();
+
+    let query_msg = QueryMsg::GetRemainingCapacity {
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+    };
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3600);
+    let res = query(deps.as_ref(), env.clone(), query_msg.clone()).unwrap();
+    let value: Vec<RemainingCapacity> = from_binary(&res).unwrap();
+    assert_eq!(
+        value[0],
+        RemainingCapacity {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            name: format!("weekly"),
+            used_in: 0_u32.into(),
+            used_out: 30_u32.into(),
+            remaining_in: 130_u32.into(),
+            remaining_out: 70_u32.into(),
+            period_end: mock_env().block.time.plus_seconds(RESET_TIME_WEEKLY),
+            seconds_to_reset: RESET_TIME_WEEKLY - 3600,
+        }
+    );
+    assert_eq!(value[1].channel_id, "any");
+    assert_eq!(value[1].used_out, Uint256::from(30_u32));
+    assert_eq!(value[1].remaining_out, Uint256::from(20_u32));
+
+    // Once the daily period is over, its whole capacity is available again
+    env.block.time = mock_env().block.time.plus_seconds(RESET_TIME_DAILY + 1);
+    let res = query(deps.as_ref(), env, query_msg).unwrap();
+    let value: Vec<RemainingCapacity> = from_binary(&res).unwrap();
+    assert_eq!(value[0].used_out, Uint256::from(30_u32));
+    assert_eq!(value[1].used_out, Uint256::from(0_u32));
+    assert_eq!(value[1].remaining_out, Uint256::from(50_u32));
+    assert_eq!(value[1].seconds_to_reset, RESET_TIME_DAILY);
+}
+
+#[test] // Tests listing the configured paths page by page
+fn list_paths() {
+    let mut deps = mock_dependencies();
+
+    let quotas = vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)];
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![
+            PathMsg::new("channel-0", "denom-a", quotas.clone()),
+            PathMsg::new("channel-0", "denom-b", quotas.clone()),
+            PathMsg::new("channel-1", "denom-a", quotas.clone()),
+            PathMsg::new("any", "denom-a", quotas),
+        ],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // Transfers through paths without quotas of their own don't store them
+    deps.querier.set_supply("denom-a", 1_000_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel-2"),
+        denom: format!("denom-a"),
+        funds: 1_u32.into()
+    );
+    sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+    let channel_2 = (format!("channel-2"), format!("denom-a"));
+    assert!(!RATE_LIMIT_TRACKERS.has(&deps.storage, channel_2));
+
+    let list = |deps: &cosmwasm_std::OwnedDeps<_, _, _>, msg: QueryMsg| -> Vec<(String, String)> {
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: Vec<PathRateLimits> = from_binary(&res).unwrap();
+        value
+            .into_iter()
+            .map(|path| (path.channel_id, path.denom))
+            .collect()
+    };
+    let paths = |paths: &[(&str, &str)]| -> Vec<(String, String)> {
+        paths
+            .iter()
+            .map(|(channel_id, denom)| (channel_id.to_string(), denom.to_string()))
+            .collect()
+    };
+
+    let msg = QueryMsg::ListPaths {
+        start_after: None,
+        limit: Some(2),
+    };
+    assert_eq!(
+        list(&deps, msg),
+        paths(&[("any", "denom-a"), ("channel-0", "denom-a")])
+    );
+    let msg = QueryMsg::ListPaths {
+        start_after: Some((format!("channel-0"), format!("denom-a"))),
+        limit: Some(2),
+    };
+    assert_eq!(
+        list(&deps, msg),
+        paths(&[("channel-0", "denom-b"), ("channel-1", "denom-a")])
+    );
+    let msg = QueryMsg::ListPaths {
+        start_after: Some((format!("channel-1"), format!("denom-a"))),
+        limit: None,
+    };
+    assert_eq!(list(&deps, msg), paths(&[]));
+
+    let msg = QueryMsg::ListPathsForChannel {
+        channel_id: format!("channel-0"),
+        start_after: None,
+        limit: None,
+    };
+    assert_eq!(
+        list(&deps, msg),
+        paths(&[("channel-0", "denom-a"), ("channel-0", "denom-b")])
+    );
+
+    let msg = QueryMsg::ListPathsForDenom {
+        denom: format!("denom-a"),
+        start_after: Some(format!("any")),
+        limit: None,
+    };
+    assert_eq!(
+        list(&deps, msg.clone()),
+        paths(&[("channel-0", "denom-a"), ("channel-1", "denom-a")])
+    );
+
+    // Removed paths are no longer listed
+    let remove_msg = ExecuteMsg::RemovePath {
+        channel_id: format!("channel-1"),
+        denom: format!("denom-a"),
+    };
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        remove_msg,
+    )
+    .unwrap();
+    assert_eq!(list(&deps, msg), paths(&[("channel-0", "denom-a")]));
+}
+
+#[test]
+fn rotate_admins() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: Some(Addr::unchecked("guardian")),
+        paths: vec![],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let propose = |role: AdminRole, address: &str| ExecuteMsg::ProposeAdmin {
+        role,
+        address: Addr::unchecked(address),
+    };
+
+    // Only governance can propose a new admin
+    for sender in [IBC_ADDR, "guardian", "new_gov"] {
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(sender, &[]),
+            propose(AdminRole::GovModule, "new_gov"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        propose(AdminRole::GovModule, "new_gov"),
+    )
+    .unwrap();
+    assert_eq!(res.events[0].ty, "propose_admin");
+    assert_eq!(
+        res.events[0].attributes[0],
+        Attribute::new("role", "gov_module")
+    );
+    assert_eq!(
+        res.events[0].attributes[1],
+        Attribute::new("proposed", "new_gov")
+    );
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        propose(AdminRole::IbcModule, "new_ibc"),
+    )
+    .unwrap();
+
+    // The current admins keep their role until the proposal is accepted
+    let query_msg = QueryMsg::GetConfig {};
+    let res = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
+    let config: Config = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        Config {
+            gov_module: Addr::unchecked(GOV_ADDR),
+            ibc_module: Addr::unchecked(IBC_ADDR),
+            guardian: Some(Addr::unchecked("guardian")),
+            timelock: 0,
+            pending_gov_module: Some(Addr::unchecked("new_gov")),
+            pending_ibc_module: Some(Addr::unchecked("new_ibc")),
+            verbose_responses: false,
+        }
+    );
+
+    // Only the proposed address can accept, and only for its own role
+    for (sender, role) in [
+        (GOV_ADDR, AdminRole::GovModule),
+        ("new_ibc", AdminRole::GovModule),
+        ("new_gov", AdminRole::IbcModule),
+    ] {
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(sender, &[]),
+            ExecuteMsg::AcceptAdmin { role },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("new_gov", &[]),
+        ExecuteMsg::AcceptAdmin {
+            role: AdminRole::GovModule,
+        },
+    )
+    .unwrap();
+    assert_eq!(res.events[0].ty, "accept_admin");
+    assert_eq!(
+        res.events[0].attributes[1],
+        Attribute::new("previous", GOV_ADDR)
+    );
+    assert_eq!(
+        res.events[0].attributes[2],
+        Attribute::new("new", "new_gov")
+    );
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("new_ibc", &[]),
+        ExecuteMsg::AcceptAdmin {
+            role: AdminRole::IbcModule,
+        },
+    )
+    .unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+    let config: Config = from_binary(&res).unwrap();
+    assert_eq!(config.gov_module, Addr::unchecked("new_gov"));
+    assert_eq!(config.ibc_module, Addr::unchecked("new_ibc"));
+    assert_eq!(config.pending_gov_module, None);
+    assert_eq!(config.pending_ibc_module, None);
+
+    // The previous governance module has lost its permissions
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        propose(AdminRole::GovModule, GOV_ADDR),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+
+    // And the proposal can't be accepted twice
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("new_gov", &[]),
+        ExecuteMsg::AcceptAdmin {
+            role: AdminRole::GovModule,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+}
+
+#[test] // Tests that governance can choose where the channel value of a path comes from
+fn channel_value_sources() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new(
+            "channel",
+            "denom",
+            vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        )],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    enable_verbose_responses(deps.as_mut());
+
+    let set_source =
+        |channel_id: &str, source: ChannelValueSource| ExecuteMsg::SetChannelValueSource {
+            channel_id: channel_id.to_string(),
+            denom: format!("denom"),
+            source,
+        };
+    let fixed = ChannelValueSource::Fixed {
+        value: 2000_u32.into(),
+    };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(IBC_ADDR, &[]),
+        set_source("channel", fixed.clone()),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        set_source("channel", fixed.clone()),
+    )
+    .unwrap();
+
+    // The fixed value is used instead of the supply of 1000
+    deps.querier.set_supply("denom", 1_000_u32);
+    let send_msg = test_msg_send!(
+        channel_id: format!("channel"),
+        denom: format!("denom"),
+        funds: 150_u32.into()
+    );
+    let res = sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+    assert_eq!(res.attributes[6], Attribute::new("weekly_max_out", "200"));
+
+    // Sources set for any channel apply to every path without their own source
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        set_source("any", ChannelValueSource::Escrow),
+    )
+    .unwrap();
+    let source_of = |deps: &OwnedDeps<_, _, _>, channel_id: &str| -> ChannelValueSource {
+        let query_msg = QueryMsg::GetChannelValueSource {
+            channel_id: channel_id.to_string(),
+            denom: format!("denom"),
+        };
+        from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap()
+    };
+    assert_eq!(source_of(&deps, "channel"), fixed);
+    assert_eq!(
+        source_of(&deps, "other_channel"),
+        ChannelValueSource::Escrow
+    );
+
+    // Going back to the supply removes the entry
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(GOV_ADDR, &[]),
+        set_source("any", ChannelValueSource::Supply),
+    )
+    .unwrap();
+    assert_eq!(
+        source_of(&deps, "other_channel"),
+        ChannelValueSource::Supply
+    );
+}
+
+#[test] // Tests that channel quotas add up the weighted value of every denom
+fn channel_quotas() {
+    let mut deps = mock_dependencies();
+
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // Channel quotas can only use absolute amounts
+    let set_quotas = |quotas: Vec<QuotaMsg>| ExecuteMsg::SetChannelQuotas {
+        channel_id: format!("channel"),
+        quotas,
+    };
+    let info = mock_info(IBC_ADDR, &[]);
+    let quota = QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10);
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        info.clone(),
+        set_quotas(vec![quota]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::InvalidChannelQuota { .. }));
+    let quota = QuotaMsg::absolute("daily", RESET_TIME_DAILY, 1_000_u32, 1_000_u32);
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        info.clone(),
+        set_quotas(vec![quota]),
+    )
+    .unwrap();
+
+    // Only the gov module can set the weights
+    let set_weight = |denom: &str, weight: &str| ExecuteMsg::SetDenomWeight {
+        denom: denom.to_string(),
+        weight: Some(Decimal::from_str(weight).unwrap()),
+    };
+    let err = execute(deps.as_mut(), mock_env(), info, set_weight("uatom", "1")).unwrap_err();
+    assert!(matches!(err, ContractError::Unauthorized {}));
+    let info = mock_info(GOV_ADDR, &[]);
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        info.clone(),
+        set_weight("uatom", "1"),
+    )
+    .unwrap();
+    execute(deps.as_mut(), mock_env(), info, set_weight("uosmo", "0.5")).unwrap();
+    let query_msg = QueryMsg::GetDenomWeight {
+        denom: format!("uosmo"),
+    };
+    let weight: Option<Decimal> =
+        from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+    assert_eq!(weight, Some(Decimal::percent(50)));
+
+    let packet = |sequence: u64, channel_id: &str, denom: &str, funds: u32| {
+        Packet::mock(
+            channel_id.to_string(),
+            channel_id.to_string(),
+            denom.to_string(),
+            funds.into(),
+        )
+        .with_sequence(sequence)
+    };
+    let send = |sequence: u64, denom: &str, funds: u32| SudoMsg::SendPacket {
+        packet: packet(sequence, "channel", denom, funds),
+    };
+
+    // Both denoms count towards the same quota: 600 + 800 * 0.5
+    sudo(deps.as_mut(), mock_env(), send(1, "uatom", 600)).unwrap();
+    sudo(deps.as_mut(), mock_env(), send(2, "uosmo", 800)).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send(3, "uatom", 1)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    // Denoms without a weight, and other channels, are not limited
+    sudo(deps.as_mut(), mock_env(), send(4, "ujuno", 10_000)).unwrap();
+    let msg = SudoMsg::SendPacket {
+        packet: packet(5, "other_channel", "uatom", 10_000),
+    };
+    sudo(deps.as_mut(), mock_env(), msg).unwrap();
+
+    let query_msg = QueryMsg::GetChannelQuotas {
+        channel_id: format!("channel"),
+    };
+    let limits: Vec<RateLimit> =
+        from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+    assert_eq!(limits[0].flow.outflow, Uint256::from(1_000_u32));
+
+    // Undoing a send reverts its weighted value
+    let msg = SudoMsg::UndoSend {
+        packet: packet(2, "channel", "uosmo", 800),
+    };
+    let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+    assert!(res
+        .attributes
+        .contains(&Attribute::new("whole_channel", "true")));
+    sudo(deps.as_mut(), mock_env(), send(6, "uatom", 400)).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send(7, "uosmo", 2)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+}
+
+#[test] // Tests that priced quotas measure transfers in the reference denom
+fn priced_quotas() {
+    let mut deps = mock_dependencies();
+
+    let quota = QuotaMsg::absolute("daily", RESET_TIME_DAILY, 1_000_u32, 1_000_u32)
+        .priced_in("uusdc", 1, 3600);
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("any", "uatom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+    // Percentages of the channel value can't be priced
+    let msg = ExecuteMsg::AddPath {
+        channel_id: format!("any"),
+        denom: format!("uosmo"),
+        quotas: vec![QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10).priced_in("uusdc", 2, 3600)],
+    };
+    let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidPricedQuota { .. }));
+
+    // The TWAP window must be within the records kept by the pools
+    for window in [0, MAX_PRICE_WINDOW + 1] {
+        let quota = QuotaMsg::absolute("daily", RESET_TIME_DAILY, 1_000_u32, 1_000_u32)
+            .priced_in("uusdc", 2, window);
+        let msg = ExecuteMsg::AddPath {
+            channel_id: format!("any"),
+            denom: format!("uosmo"),
+            quotas: vec![quota],
+        };
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidPriceWindow {
+                quota_id: format!("daily"),
+                max: MAX_PRICE_WINDOW,
+            }
+        );
+    }
+
+    let send = |sequence: u64, funds: u32| SudoMsg::SendPacket {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("uatom"),
+            funds.into(),
+        )
+        .with_sequence(sequence),
+    };
+
+    // Transfers are rejected until the quota has a price
+    let err = sudo(deps.as_mut(), mock_env(), send(1, 50)).unwrap_err();
+    assert!(matches!(err, ContractError::PriceUnavailable { .. }));
+
+    // 50 atoms at 10 usdc are worth 500 usdc
+    let price = |price: u64| Decimal::from_ratio(price, 1_u32);
+    deps.querier.set_twap(1, "uatom", "uusdc", price(10));
+    sudo(deps.as_mut(), mock_env(), send(2, 50)).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send(3, 60)).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::RateLimitExceded { amount, .. } if amount == Uint256::from(600_u32)
+    ));
+
+    // Without a TWAP, the last price is used
+    deps.querier.remove_twap(1, "uatom", "uusdc");
+    sudo(deps.as_mut(), mock_env(), send(4, 40)).unwrap();
+
+    // Undoing a send gives back the value it was counted with, not its current value
+    deps.querier.set_twap(1, "uatom", "uusdc", price(20));
+    let msg = SudoMsg::UndoSend {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("uatom"),
+            50_u32.into(),
+        )
+        .with_sequence(2),
+    };
+    sudo(deps.as_mut(), mock_env(), msg).unwrap();
+    sudo(deps.as_mut(), mock_env(), send(5, 25)).unwrap();
+    let err = sudo(deps.as_mut(), mock_env(), send(6, 6)).unwrap_err();
+    assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+    let query_msg = QueryMsg::GetQuotas {
+        channel_id: format!("any"),
+        denom: format!("uatom"),
+    };
+    let limits: Vec<RateLimit> =
+        from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+    assert_eq!(limits[0].quota.last_price, Some(price(20)));
+    assert_eq!(limits[0].flow.outflow, Uint256::from(900_u32));
+}
+
+#[cfg(not(feature = "verbose_responses"))]
+#[test] // Tests that the usage of the quotas is only in the responses once governance turns it on
+fn verbose_responses() {
+    let mut deps = mock_dependencies();
+
+    let quota = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10);
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("any", "denom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 3_000_u32);
+    let send = |sequence: u64| SudoMsg::SendPacket {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            100_u32.into(),
+        )
+        .with_sequence(sequence),
+    };
+    let keys = |res: &cosmwasm_std::Response| -> Vec<String> {
+        res.attributes.iter().map(|attr| attr.key.clone()).collect()
+    };
+    let is_verbose = |deps: &OwnedDeps<_, _, _>| {
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap();
+        from_binary::<Config>(&res).unwrap().verbose_responses
+    };
+
+    // By default, responses only say which path the packet used
+    assert!(!is_verbose(&deps));
+    let res = sudo(deps.as_mut(), mock_env(), send(1)).unwrap();
+    assert_eq!(keys(&res), vec!["method", "channel_id", "denom"]);
+
+    // Only governance can turn on the usage of the quotas
+    let msg = ExecuteMsg::SetVerboseResponses { enabled: true };
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(IBC_ADDR, &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized {});
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    assert!(is_verbose(&deps));
+
+    let res = sudo(deps.as_mut(), mock_env(), send(2)).unwrap();
+    assert_eq!(
+        keys(&res),
+        vec![
+            "method",
+            "channel_id",
+            "denom",
+            "weekly_used_in",
+            "weekly_used_out",
+            "weekly_max_in",
+            "weekly_max_out",
+            "weekly_period_end",
+        ]
+    );
+    assert_eq!(res.attributes[4], Attribute::new("weekly_used_out", "200"));
+
+    // And off again
+    let msg = ExecuteMsg::SetVerboseResponses { enabled: false };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let res = sudo(deps.as_mut(), mock_env(), send(3)).unwrap();
+    assert_eq!(res.attributes.len(), 3);
+}
+
+#[cfg(feature = "verbose_responses")]
+#[test] // Tests that builds with the verbose_responses feature always include the usage of the quotas
+fn verbose_responses_feature() {
+    let mut deps = mock_dependencies();
+
+    let quota = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10);
+    let msg = InstantiateMsg {
+        gov_module: Addr::unchecked(GOV_ADDR),
+        ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
+        paths: vec![PathMsg::new("any", "denom", vec![quota])],
+    };
+    let info = mock_info(GOV_ADDR, &vec![]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps.querier.set_supply("denom", 3_000_u32);
+    let send = |sequence: u64| SudoMsg::SendPacket {
+        packet: Packet::mock(
+            format!("channel"),
+            format!("channel"),
+            format!("denom"),
+            100_u32.into(),
+        )
+        .with_sequence(sequence),
+    };
+
+    // The usage is there even though governance never turned it on
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap();
+    assert!(!from_binary::<Config>(&res).unwrap().verbose_responses);
+    let res = sudo(deps.as_mut(), mock_env(), send(1)).unwrap();
+    assert_eq!(res.attributes.len(), 8);
+    assert_eq!(res.attributes[4], Attribute::new("weekly_used_out", "100"));
+
+    // And turning it off doesn't remove it
+    let msg = ExecuteMsg::SetVerboseResponses { enabled: false };
+    execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+    let res = sudo(deps.as_mut(), mock_env(), send(2)).unwrap();
+    assert_eq!(res.attributes[4], Attribute::new("weekly_used_out", "200"));
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::{StdError, Timestamp, Uint256};
+use cosmwasm_std::{Decimal, StdError, Timestamp, Uint256};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -20,10 +20,105 @@ pub enum ContractError {
         reset: Timestamp,
     },
 
+    #[error("IBC Rate Limit exceeded for address {address} on {channel}/{denom}. Tried to transfer {amount} which exceeds capacity on the '{quota_name}' quota ({used}/{max}). Try again after {reset:?}")]
+    AddressRateLimitExceded {
+        address: String,
+        channel: String,
+        denom: String,
+        amount: Uint256,
+        quota_name: String,
+        used: Uint256,
+        max: Uint256,
+        reset: Timestamp,
+    },
+
+    #[error("IBC transfers for {channel}/{denom} are paused")]
+    Paused { channel: String, denom: String },
+
     #[error("Quota {quota_id} not found for channel {channel_id}")]
     QuotaNotFound {
         quota_id: String,
         channel_id: String,
         denom: String,
     },
+
+    #[error("Quota {quota_id} already exists for channel {channel_id}")]
+    QuotaAlreadyExists {
+        quota_id: String,
+        channel_id: String,
+        denom: String,
+    },
+
+    #[error("Quota names cannot be empty")]
+    EmptyQuotaName {},
+
+    #[error("Quota {quota_id} must have a duration greater than zero")]
+    ZeroQuotaDuration { quota_id: String },
+
+    #[error("Quota {quota_id} can't have a duration of more than {max} seconds")]
+    QuotaDurationTooLong { quota_id: String, max: u64 },
+
+    #[error("Quota {quota_id} must limit a percentage of the channel value, an amount, or both")]
+    MissingQuotaLimits { quota_id: String },
+
+    #[error("Quota {quota_id} must split its sliding window into at least one bucket")]
+    ZeroQuotaBuckets { quota_id: String },
+
+    #[error("Quota {quota_id} can't split its sliding window into more than {max} buckets")]
+    TooManyQuotaBuckets { quota_id: String, max: u64 },
+
+    #[error("Quota {quota_id} can only be aligned if it uses fixed periods")]
+    InvalidQuotaAlignment { quota_id: String },
+
+    #[error("Quota {quota_id} applies to a whole channel, so it can only limit absolute amounts")]
+    InvalidChannelQuota { quota_id: String },
+
+    #[error(
+        "Quota {quota_id} is priced in a reference denom, so it can only limit absolute amounts"
+    )]
+    InvalidPricedQuota { quota_id: String },
+
+    #[error("Quota {quota_id} must average prices over a window of 1 to {max} seconds")]
+    InvalidPriceWindow { quota_id: String, max: u64 },
+
+    #[error("There is no price for {denom} in {reference_denom} (pool {pool_id})")]
+    PriceUnavailable {
+        denom: String,
+        reference_denom: String,
+        pool_id: u64,
+    },
+
+    #[error("Quota {quota_id} has a percentage of {percentage}, which is over 100")]
+    InvalidQuotaPercentage {
+        quota_id: String,
+        percentage: Decimal,
+    },
+
+    #[error("Quota {quota_id} is defined more than once for channel {channel_id}")]
+    DuplicateQuotaName {
+        quota_id: String,
+        channel_id: String,
+        denom: String,
+    },
+
+    #[error("No quotas provided for channel {channel_id}")]
+    EmptyQuotaList { channel_id: String, denom: String },
+
+    #[error("Pending change {id} not found")]
+    PendingChangeNotFound { id: u64 },
+
+    #[error("Pending change {id} can't be executed until {executable_at}")]
+    TimelockNotExpired { id: u64, executable_at: Timestamp },
+
+    #[error("Cannot migrate from contract {previous_contract} to {contract}")]
+    InvalidMigrationContract {
+        previous_contract: String,
+        contract: String,
+    },
+
+    #[error("Cannot migrate from version {previous_version} to {version}")]
+    InvalidMigrationVersion {
+        previous_version: String,
+        version: String,
+    },
 }
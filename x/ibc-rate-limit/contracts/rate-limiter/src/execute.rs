@@ -1,7 +1,14 @@
-use crate::msg::{PathMsg, QuotaMsg};
-use crate::state::{Flow, Path, RateLimit, GOVMODULE, IBCMODULE, RATE_LIMIT_TRACKERS};
+use crate::msg::{AdminRole, ExecuteMsg, PathMsg, PauseScope, QuotaMsg};
+use crate::state::{
+    build_channel_quotas, build_quotas, loosens_quotas, remove_address_trackers,
+    ChannelValueSource, ExemptionMode, Path, PendingChange, Quota, RateLimit, ADDRESS_QUOTAS,
+    CHANNEL_RATE_LIMIT_TRACKERS, CHANNEL_VALUE_SOURCES, DENOM_WEIGHTS, EXEMPTIONS, GOVMODULE,
+    GUARDIAN, IBCMODULE, NEXT_PENDING_CHANGE_ID, PATHS_BY_DENOM, PAUSED, PENDING_CHANGES,
+    PENDING_GOVMODULE, PENDING_IBCMODULE, RATE_LIMIT_TRACKERS, TIMELOCK, VERBOSE_RESPONSES,
+};
 use crate::ContractError;
-use cosmwasm_std::{Addr, DepsMut, Response, Timestamp};
+use cosmwasm_std::{Addr, Decimal, Deps, DepsMut, Empty, Event, Response, Storage, Timestamp};
+use cw_storage_plus::Item;
 
 pub fn add_new_paths(
     deps: DepsMut,
@@ -10,17 +17,25 @@ pub fn add_new_paths(
 ) -> Result<(), ContractError> {
     for path_msg in path_msgs {
         let path = Path::new(path_msg.channel_id, path_msg.denom);
+        if path_msg.quotas.is_empty() {
+            return Err(ContractError::EmptyQuotaList {
+                channel_id: path.channel,
+                denom: path.denom,
+            });
+        }
+        let quotas = build_quotas(&path, &path_msg.quotas)?;
 
+        PATHS_BY_DENOM.save(
+            deps.storage,
+            (path.denom.clone(), path.channel.clone()),
+            &Empty {},
+        )?;
         RATE_LIMIT_TRACKERS.save(
             deps.storage,
             path.into(),
-            &path_msg
-                .quotas
-                .iter()
-                .map(|q| RateLimit {
-                    quota: q.into(),
-                    flow: Flow::new(0_u128, 0_u128, now, q.duration),
-                })
+            &quotas
+                .into_iter()
+                .map(|quota| RateLimit::new(quota, now))
                 .collect(),
         )?
     }
@@ -63,60 +78,714 @@ pub fn try_remove_path(
 
     let path = Path::new(&channel_id, &denom);
     RATE_LIMIT_TRACKERS.remove(deps.storage, path.into());
+    PATHS_BY_DENOM.remove(deps.storage, (denom.clone(), channel_id.clone()));
     Ok(Response::new()
         .add_attribute("method", "try_remove_channel")
         .add_attribute("denom", denom)
         .add_attribute("channel_id", channel_id))
 }
 
-// Reset specified quote_id for the given channel_id
-pub fn try_reset_path_quota(
+pub fn try_add_quota(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    denom: String,
+    quota: QuotaMsg,
+    now: Timestamp,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != ibc_module && sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let path = Path::new(&channel_id, &denom);
+    let mut limits = RATE_LIMIT_TRACKERS
+        .may_load(deps.storage, (&path).into())?
+        .unwrap_or_default();
+    if limits.iter().any(|limit| limit.quota.name == quota.name) {
+        return Err(ContractError::QuotaAlreadyExists {
+            quota_id: quota.name,
+            channel_id,
+            denom,
+        });
+    }
+    limits.push(RateLimit::new(Quota::try_from(&quota)?, now));
+    RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &limits)?;
+    PATHS_BY_DENOM.save(deps.storage, (denom.clone(), channel_id.clone()), &Empty {})?;
+
+    Ok(Response::new()
+        .add_attribute("method", "try_add_quota")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom)
+        .add_attribute("quota_id", quota.name))
+}
+
+pub fn try_update_quota(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    denom: String,
+    quota: QuotaMsg,
+    now: Timestamp,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != ibc_module && sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let path = Path::new(&channel_id, &denom);
+    let mut limits = RATE_LIMIT_TRACKERS
+        .may_load(deps.storage, (&path).into())?
+        .unwrap_or_default();
+    match limits
+        .iter_mut()
+        .find(|limit| limit.quota.name == quota.name)
+    {
+        Some(limit) => limit.update_quota(Quota::try_from(&quota)?, now),
+        None => {
+            return Err(ContractError::QuotaNotFound {
+                quota_id: quota.name,
+                channel_id,
+                denom,
+            })
+        }
+    }
+    RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &limits)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "try_update_quota")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom)
+        .add_attribute("quota_id", quota.name))
+}
+
+pub fn try_remove_quota(
     deps: DepsMut,
     sender: Addr,
     channel_id: String,
     denom: String,
     quota_id: String,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != ibc_module && sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let path = Path::new(&channel_id, &denom);
+    let mut limits = RATE_LIMIT_TRACKERS
+        .may_load(deps.storage, (&path).into())?
+        .unwrap_or_default();
+    let count = limits.len();
+    limits.retain(|limit| limit.quota.name != quota_id);
+    if limits.len() == count {
+        return Err(ContractError::QuotaNotFound {
+            quota_id,
+            channel_id,
+            denom,
+        });
+    }
+    if limits.is_empty() {
+        RATE_LIMIT_TRACKERS.remove(deps.storage, path.into());
+        PATHS_BY_DENOM.remove(deps.storage, (denom.clone(), channel_id.clone()));
+    } else {
+        RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &limits)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "try_remove_quota")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom)
+        .add_attribute("quota_id", quota_id))
+}
+
+// Replacing the quotas doesn't touch the address trackers. The flow of each
+// address is checked against the new quotas on its next transfer (see
+// RateLimit::for_quotas). Removing them drops the trackers of every address
+pub fn try_set_address_quotas(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    denom: String,
+    quotas: Vec<QuotaMsg>,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != ibc_module && sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let path = Path::new(&channel_id, &denom);
+    if quotas.is_empty() {
+        ADDRESS_QUOTAS.remove(deps.storage, (&path).into());
+        remove_address_trackers(deps.storage, &path)?;
+    } else {
+        let quotas = build_quotas(&path, &quotas)?;
+        ADDRESS_QUOTAS.save(deps.storage, path.into(), &quotas)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "try_set_address_quotas")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom))
+}
+
+// Quotas that keep their name also keep their flow, so replacing the quotas of
+// a channel doesn't reset the usage of the current period
+pub fn try_set_channel_quotas(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    quotas: Vec<QuotaMsg>,
     now: Timestamp,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != ibc_module && sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if quotas.is_empty() {
+        CHANNEL_RATE_LIMIT_TRACKERS.remove(deps.storage, channel_id.clone());
+    } else {
+        let quotas = build_channel_quotas(&channel_id, &quotas)?;
+        let existing = CHANNEL_RATE_LIMIT_TRACKERS
+            .may_load(deps.storage, channel_id.clone())?
+            .unwrap_or_default();
+        let limits = RateLimit::for_quotas(&quotas, existing, now);
+        CHANNEL_RATE_LIMIT_TRACKERS.save(deps.storage, channel_id.clone(), &limits)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "try_set_channel_quotas")
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn try_set_denom_weight(
+    deps: DepsMut,
+    sender: Addr,
+    denom: String,
+    weight: Option<Decimal>,
 ) -> Result<Response, ContractError> {
     let gov_module = GOVMODULE.load(deps.storage)?;
     if sender != gov_module {
         return Err(ContractError::Unauthorized {});
     }
 
-    let path = Path::new(&channel_id, &denom);
-    RATE_LIMIT_TRACKERS.update(deps.storage, path.into(), |maybe_rate_limit| {
-        match maybe_rate_limit {
-            None => Err(ContractError::QuotaNotFound {
-                quota_id,
-                channel_id: channel_id.clone(),
-                denom: denom.clone(),
+    match weight {
+        Some(weight) => DENOM_WEIGHTS.save(deps.storage, denom.clone(), &weight)?,
+        None => DENOM_WEIGHTS.remove(deps.storage, denom.clone()),
+    }
+    Ok(Response::new()
+        .add_attribute("method", "try_set_denom_weight")
+        .add_attribute("denom", denom)
+        .add_attribute(
+            "weight",
+            weight.map_or_else(|| "none".to_string(), |weight| weight.to_string()),
+        ))
+}
+
+pub fn try_add_exemption(
+    deps: DepsMut,
+    sender: Addr,
+    address: String,
+    channel_id: Option<String>,
+    denom: Option<String>,
+    mode: ExemptionMode,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let channel_id = channel_id.unwrap_or_else(|| "any".to_string());
+    let denom = denom.unwrap_or_else(|| "any".to_string());
+    EXEMPTIONS.save(
+        deps.storage,
+        (address.clone(), channel_id.clone(), denom.clone()),
+        &mode,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "try_add_exemption")
+        .add_attribute("address", address)
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom))
+}
+
+pub fn try_remove_exemption(
+    deps: DepsMut,
+    sender: Addr,
+    address: String,
+    channel_id: Option<String>,
+    denom: Option<String>,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let channel_id = channel_id.unwrap_or_else(|| "any".to_string());
+    let denom = denom.unwrap_or_else(|| "any".to_string());
+    EXEMPTIONS.remove(
+        deps.storage,
+        (address.clone(), channel_id.clone(), denom.clone()),
+    );
+
+    Ok(Response::new()
+        .add_attribute("method", "try_remove_exemption")
+        .add_attribute("address", address)
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom))
+}
+
+// The guardian is expected to react to exploits faster than governance.
+// Governance can also pause and unpause in case there is no guardian.
+fn check_is_gov_or_guardian(deps: &DepsMut, sender: &Addr) -> Result<(), ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    let guardian = GUARDIAN.may_load(deps.storage)?;
+    if *sender != gov_module && Some(sender) != guardian.as_ref() {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+pub fn try_pause(
+    deps: DepsMut,
+    sender: Addr,
+    scope: PauseScope,
+    now: Timestamp,
+) -> Result<Response, ContractError> {
+    check_is_gov_or_guardian(&deps, &sender)?;
+
+    let path: Path = scope.into();
+    PAUSED.save(deps.storage, (&path).into(), &now)?;
+    Ok(Response::new()
+        .add_attribute("method", "try_pause")
+        .add_attribute("channel_id", path.channel)
+        .add_attribute("denom", path.denom))
+}
+
+pub fn try_unpause(
+    deps: DepsMut,
+    sender: Addr,
+    scope: PauseScope,
+) -> Result<Response, ContractError> {
+    check_is_gov_or_guardian(&deps, &sender)?;
+
+    let path: Path = scope.into();
+    PAUSED.remove(deps.storage, (&path).into());
+    Ok(Response::new()
+        .add_attribute("method", "try_unpause")
+        .add_attribute("channel_id", path.channel)
+        .add_attribute("denom", path.denom))
+}
+
+pub fn try_set_guardian(
+    deps: DepsMut,
+    sender: Addr,
+    guardian: Option<Addr>,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let response = Response::new().add_attribute("method", "try_set_guardian");
+    match guardian {
+        Some(guardian) => {
+            GUARDIAN.save(deps.storage, &guardian)?;
+            Ok(response.add_attribute("guardian", guardian))
+        }
+        None => {
+            GUARDIAN.remove(deps.storage);
+            Ok(response.add_attribute("guardian", "none"))
+        }
+    }
+}
+
+fn admin_items(role: &AdminRole) -> (Item<'static, Addr>, Item<'static, Addr>) {
+    match role {
+        AdminRole::GovModule => (GOVMODULE, PENDING_GOVMODULE),
+        AdminRole::IbcModule => (IBCMODULE, PENDING_IBCMODULE),
+    }
+}
+
+// Handing over a role is done in two steps so that a typo in a governance
+// proposal can't lock the contract. The current holder keeps the role until
+// the proposed address accepts it.
+pub fn try_propose_admin(
+    deps: DepsMut,
+    sender: Addr,
+    role: AdminRole,
+    address: Addr,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let (_, pending) = admin_items(&role);
+    pending.save(deps.storage, &address)?;
+    Ok(Response::new()
+        .add_attribute("method", "try_propose_admin")
+        .add_event(
+            Event::new("propose_admin")
+                .add_attribute("role", role.to_string())
+                .add_attribute("proposed", address),
+        ))
+}
+
+pub fn try_accept_admin(
+    deps: DepsMut,
+    sender: Addr,
+    role: AdminRole,
+) -> Result<Response, ContractError> {
+    let (current, pending) = admin_items(&role);
+    if pending.may_load(deps.storage)?.as_ref() != Some(&sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let previous = current.load(deps.storage)?;
+    current.save(deps.storage, &sender)?;
+    pending.remove(deps.storage);
+    Ok(Response::new()
+        .add_attribute("method", "try_accept_admin")
+        .add_event(
+            Event::new("accept_admin")
+                .add_attribute("role", role.to_string())
+                .add_attribute("previous", previous)
+                .add_attribute("new", sender),
+        ))
+}
+
+pub fn try_set_timelock(
+    deps: DepsMut,
+    sender: Addr,
+    delay: u64,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    TIMELOCK.save(deps.storage, &delay)?;
+    Ok(Response::new()
+        .add_attribute("method", "try_set_timelock")
+        .add_attribute("delay", delay.to_string()))
+}
+
+pub fn try_set_verbose_responses(
+    deps: DepsMut,
+    sender: Addr,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    VERBOSE_RESPONSES.save(deps.storage, &enabled)?;
+    Ok(Response::new()
+        .add_attribute("method", "try_set_verbose_responses")
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+// The channel value cached by the quotas is not updated until their current
+// period ends
+pub fn try_set_channel_value_source(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    denom: String,
+    source: ChannelValueSource,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let key = (channel_id.clone(), denom.clone());
+    match source {
+        ChannelValueSource::Supply => CHANNEL_VALUE_SOURCES.remove(deps.storage, key),
+        _ => CHANNEL_VALUE_SOURCES.save(deps.storage, key, &source)?,
+    }
+    Ok(Response::new()
+        .add_attribute("method", "try_set_channel_value_source")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("denom", denom))
+}
+
+// Changes that loosen the rate limits are delayed so that they can be reviewed
+// (and cancelled if needed) before they take effect. Changes that tighten the
+// limits, as well as the ones that would fail anyway, are applied right away.
+pub fn requires_timelock(deps: Deps, msg: &ExecuteMsg) -> Result<bool, ContractError> {
+    if TIMELOCK.may_load(deps.storage)?.unwrap_or_default() == 0 {
+        return Ok(false);
+    }
+
+    let path_quotas = |channel_id: &String, denom: &String| -> Result<Vec<Quota>, ContractError> {
+        let limits = RATE_LIMIT_TRACKERS
+            .may_load(deps.storage, (channel_id.clone(), denom.clone()))?
+            .unwrap_or_default();
+        Ok(limits.into_iter().map(|limit| limit.quota).collect())
+    };
+    let loosening = match msg {
+        ExecuteMsg::AddPath {
+            channel_id,
+            denom,
+            quotas,
+            // Replacing the quotas of a path resets their flows, which frees up
+            // capacity even if the new quotas are the same
+        } => match build_quotas(&Path::new(channel_id, denom), quotas) {
+            Ok(new) if !new.is_empty() => !path_quotas(channel_id, denom)?.is_empty(),
+            _ => false,
+        },
+        ExecuteMsg::RemovePath { channel_id, denom } => !path_quotas(channel_id, denom)?.is_empty(),
+        ExecuteMsg::UpdateQuota {
+            channel_id,
+            denom,
+            quota,
+        } => match Quota::try_from(quota) {
+            Ok(new) => path_quotas(channel_id, denom)?
+                .iter()
+                .any(|current| current.name == new.name && new.is_looser_than(current)),
+            Err(_) => false,
+        },
+        ExecuteMsg::RemoveQuota {
+            channel_id,
+            denom,
+            quota_id,
+        } => path_quotas(channel_id, denom)?
+            .iter()
+            .any(|current| current.name == *quota_id),
+        ExecuteMsg::ResetPathQuota {
+            channel_id,
+            denom,
+            quota_id,
+        } => path_quotas(channel_id, denom)?
+            .iter()
+            .any(|current| match quota_id {
+                Some(quota_id) => current.name == *quota_id,
+                None => true,
             }),
-            Some(mut limits) => {
-                // Q: What happens here if quote_id not found? seems like we return ok?
-                limits.iter_mut().for_each(|limit| {
-                    if limit.quota.name == quota_id.as_ref() {
-                        limit.flow.expire(now, limit.quota.duration)
-                    }
-                });
-                Ok(limits)
+        // Exempt addresses are never rejected. Only switching an existing
+        // exemption from skip to record tightens it
+        ExecuteMsg::AddExemption {
+            address,
+            channel_id,
+            denom,
+            mode,
+        } => {
+            let key = (
+                address.clone(),
+                channel_id.clone().unwrap_or_else(|| "any".to_string()),
+                denom.clone().unwrap_or_else(|| "any".to_string()),
+            );
+            !matches!(
+                (EXEMPTIONS.may_load(deps.storage, key)?, mode),
+                (Some(ExemptionMode::Skip), _)
+                    | (Some(ExemptionMode::Record), ExemptionMode::Record)
+            )
+        }
+        ExecuteMsg::SetAddressQuotas {
+            channel_id,
+            denom,
+            quotas,
+        } => {
+            let path = Path::new(channel_id, denom);
+            let current = ADDRESS_QUOTAS
+                .may_load(deps.storage, (&path).into())?
+                .unwrap_or_default();
+            match build_quotas(&path, quotas) {
+                Ok(new) => loosens_quotas(&current, &new),
+                Err(_) => false,
+            }
+        }
+        ExecuteMsg::SetChannelQuotas { channel_id, quotas } => {
+            let current: Vec<Quota> = CHANNEL_RATE_LIMIT_TRACKERS
+                .may_load(deps.storage, channel_id.clone())?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|limit| limit.quota)
+                .collect();
+            match build_channel_quotas(channel_id, quotas) {
+                Ok(new) => loosens_quotas(&current, &new),
+                Err(_) => false,
             }
         }
-    })?;
+        // A lower weight makes every transfer of the denom count for less
+        ExecuteMsg::SetDenomWeight { denom, weight } => {
+            match (DENOM_WEIGHTS.may_load(deps.storage, denom.clone())?, weight) {
+                (Some(current), Some(weight)) => *weight < current,
+                // Denoms without a weight aren't counted at all
+                (Some(_), None) => true,
+                (None, _) => false,
+            }
+        }
+        ExecuteMsg::SetTimelock { delay } => *delay < TIMELOCK.load(deps.storage)?,
+        // Values from different sources can't be compared
+        ExecuteMsg::SetChannelValueSource {
+            channel_id,
+            denom,
+            source,
+        } => {
+            let key = (channel_id.clone(), denom.clone());
+            CHANNEL_VALUE_SOURCES
+                .may_load(deps.storage, key)?
+                .unwrap_or_default()
+                != *source
+        }
+        _ => false,
+    };
+    Ok(loosening)
+}
+
+pub fn try_queue_change(
+    deps: DepsMut,
+    sender: Addr,
+    msg: ExecuteMsg,
+    now: Timestamp,
+) -> Result<Response, ContractError> {
+    let ibc_module = IBCMODULE.load(deps.storage)?;
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    let authorized = match msg {
+        ExecuteMsg::SetTimelock { .. }
+        | ExecuteMsg::SetChannelValueSource { .. }
+        | ExecuteMsg::SetDenomWeight { .. }
+        | ExecuteMsg::AddExemption { .. }
+        | ExecuteMsg::ResetPathQuota { .. } => sender == gov_module,
+        _ => sender == ibc_module || sender == gov_module,
+    };
+    if !authorized {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let id = NEXT_PENDING_CHANGE_ID
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    NEXT_PENDING_CHANGE_ID.save(deps.storage, &(id + 1))?;
+    let change = PendingChange {
+        id,
+        proposer: sender,
+        msg,
+        executable_at: now.plus_seconds(TIMELOCK.load(deps.storage)?),
+    };
+    PENDING_CHANGES.save(deps.storage, id, &change)?;
 
     Ok(Response::new()
+        .add_attribute("method", "try_queue_change")
+        .add_attribute("id", id.to_string())
+        .add_attribute("executable_at", change.executable_at.seconds().to_string()))
+}
+
+/// Removes a pending change from the queue so that it can be executed
+pub fn take_pending_change(
+    storage: &mut dyn Storage,
+    id: u64,
+    now: Timestamp,
+) -> Result<PendingChange, ContractError> {
+    let change = PENDING_CHANGES
+        .may_load(storage, id)?
+        .ok_or(ContractError::PendingChangeNotFound { id })?;
+    if now < change.executable_at {
+        return Err(ContractError::TimelockNotExpired {
+            id,
+            executable_at: change.executable_at,
+        });
+    }
+    PENDING_CHANGES.remove(storage, id);
+    Ok(change)
+}
+
+pub fn try_cancel_pending_change(
+    deps: DepsMut,
+    sender: Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    check_is_gov_or_guardian(&deps, &sender)?;
+
+    if !PENDING_CHANGES.has(deps.storage, id) {
+        return Err(ContractError::PendingChangeNotFound { id });
+    }
+    PENDING_CHANGES.remove(deps.storage, id);
+    Ok(Response::new()
+        .add_attribute("method", "try_cancel_pending_change")
+        .add_attribute("id", id.to_string()))
+}
+
+// Reset specified quote_id for the given channel_id. If no quota_id is
+// specified, all the quotas of the path are reset. The "any" channel can be
+// reset like any other path.
+//
+// The flow of each quota before the reset is added to the response so that
+// there is a record of what governance reset.
+pub fn try_reset_path_quota(
+    deps: DepsMut,
+    sender: Addr,
+    channel_id: String,
+    denom: String,
+    quota_id: Option<String>,
+    now: Timestamp,
+) -> Result<Response, ContractError> {
+    let gov_module = GOVMODULE.load(deps.storage)?;
+    if sender != gov_module {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let not_found = || ContractError::QuotaNotFound {
+        quota_id: quota_id.clone().unwrap_or_else(|| "all".to_string()),
+        channel_id: channel_id.clone(),
+        denom: denom.clone(),
+    };
+
+    let path = Path::new(&channel_id, &denom);
+    let mut limits = RATE_LIMIT_TRACKERS
+        .may_load(deps.storage, (&path).into())?
+        .ok_or_else(not_found)?;
+
+    let mut response = Response::new()
         .add_attribute("method", "try_reset_channel")
-        .add_attribute("channel_id", channel_id))
+        .add_attribute("channel_id", &channel_id)
+        .add_attribute("denom", &denom);
+    let mut found = false;
+    for limit in limits.iter_mut() {
+        if quota_id.is_none() || quota_id.as_ref() == Some(&limit.quota.name) {
+            let name = &limit.quota.name;
+            response = response
+                .add_attribute(format!("{name}_inflow_before"), limit.flow.inflow)
+                .add_attribute(format!("{name}_outflow_before"), limit.flow.outflow)
+                .add_attribute(
+                    format!("{name}_period_end_before"),
+                    limit.flow.period_end.to_string(),
+                );
+            limit.reset(now);
+            found = true;
+        }
+    }
+    if !found {
+        return Err(not_found());
+    }
+    RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &limits)?;
+
+    Ok(response)
 }
 
 #[cfg(test)]
 mod tests {
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{from_binary, Addr, StdError};
+    use cosmwasm_std::testing::{mock_env, mock_info};
+    use cosmwasm_std::{from_binary, Addr, Attribute, Decimal, OwnedDeps, StdError, Uint256};
 
-    use crate::contract::{execute, query};
+    use crate::contract::{execute, query, sudo};
+    use crate::helpers::mock_dependencies;
     use crate::helpers::tests::verify_query_response;
-    use crate::msg::{ExecuteMsg, QueryMsg, QuotaMsg};
-    use crate::state::{RateLimit, GOVMODULE, IBCMODULE};
+    use crate::msg::{Config, ExecuteMsg, QueryMsg, QuotaMsg};
+    use crate::state::tests::{RESET_TIME_DAILY, RESET_TIME_MONTHLY, RESET_TIME_WEEKLY};
+    use crate::state::ExemptionMode;
+    use crate::state::{PendingChange, RateLimit, GOVMODULE, GUARDIAN, IBCMODULE};
+    use crate::{test_msg_send, ContractError};
 
     const IBC_ADDR: &str = "IBC_MODULE";
     const GOV_ADDR: &str = "GOV_MODULE";
@@ -134,11 +803,7 @@ mod tests {
         let msg = ExecuteMsg::AddPath {
             channel_id: format!("channel"),
             denom: format!("denom"),
-            quotas: vec![QuotaMsg {
-                name: "daily".to_string(),
-                duration: 1600,
-                send_recv: (3, 5),
-            }],
+            quotas: vec![QuotaMsg::new("daily", 1600, 3, 5)],
         };
         let info = mock_info(IBC_ADDR, &vec![]);
 
@@ -170,11 +835,7 @@ mod tests {
         let msg = ExecuteMsg::AddPath {
             channel_id: format!("channel2"),
             denom: format!("denom"),
-            quotas: vec![QuotaMsg {
-                name: "daily".to_string(),
-                duration: 1600,
-                send_recv: (3, 5),
-            }],
+            quotas: vec![QuotaMsg::new("daily", 1600, 3, 5)],
         };
         let info = mock_info(IBC_ADDR, &vec![]);
 
@@ -217,11 +878,7 @@ mod tests {
         let msg = ExecuteMsg::AddPath {
             channel_id: format!("channel2"),
             denom: format!("denom"),
-            quotas: vec![QuotaMsg {
-                name: "different".to_string(),
-                duration: 5000,
-                send_recv: (50, 30),
-            }],
+            quotas: vec![QuotaMsg::new("different", 5000, 50, 30)],
         };
         let info = mock_info(IBC_ADDR, &vec![]);
 
@@ -246,4 +903,473 @@ mod tests {
             env.block.time.plus_seconds(5000),
         );
     }
+
+    #[test] // Tests AddQuota, UpdateQuota and RemoveQuota messages
+    fn management_single_quotas() {
+        let mut deps = mock_dependencies();
+        IBCMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(IBC_ADDR))
+            .unwrap();
+        GOVMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(GOV_ADDR))
+            .unwrap();
+
+        let msg = ExecuteMsg::AddPath {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quotas: vec![
+                QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10),
+                QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10),
+            ],
+        };
+        let env = mock_env();
+        execute(deps.as_mut(), env.clone(), mock_info(IBC_ADDR, &[]), msg).unwrap();
+
+        deps.querier.set_supply("denom", 1_000_u32);
+        let send_msg = test_msg_send!(
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            funds: 30_u32.into()
+        );
+        sudo(deps.as_mut(), env.clone(), send_msg).unwrap();
+
+        let query_msg = QueryMsg::GetQuotas {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+        };
+        let quotas = |deps: &OwnedDeps<_, _, _>| -> Vec<RateLimit> {
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap()).unwrap()
+        };
+
+        // Adding a quota keeps the flow of the existing ones
+        let msg = ExecuteMsg::AddQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota: QuotaMsg::new("monthly", RESET_TIME_MONTHLY, 20, 20),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::QuotaAlreadyExists { .. }));
+
+        let value = quotas(&deps);
+        assert_eq!(value.len(), 3);
+        assert_eq!(value[0].flow.outflow, Uint256::from(30_u32));
+        assert_eq!(value[1].flow.outflow, Uint256::from(30_u32));
+        verify_query_response(
+            &value[2],
+            "monthly",
+            (20, 20),
+            RESET_TIME_MONTHLY,
+            0_u32.into(),
+            0_u32.into(),
+            env.block.time.plus_seconds(RESET_TIME_MONTHLY),
+        );
+
+        // Updating a quota keeps its flow and the start of its period
+        let msg = ExecuteMsg::UpdateQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota: QuotaMsg::new("weekly", RESET_TIME_WEEKLY * 2, 5, 5),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+        let value = quotas(&deps);
+        verify_query_response(
+            &value[1],
+            "weekly",
+            (5, 5),
+            RESET_TIME_WEEKLY * 2,
+            0_u32.into(),
+            30_u32.into(),
+            env.block.time.plus_seconds(RESET_TIME_WEEKLY * 2),
+        );
+        assert_eq!(value[1].quota.channel_value, Some(1_000_u32.into()));
+
+        let msg = ExecuteMsg::UpdateQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota: QuotaMsg::new("yearly", RESET_TIME_WEEKLY * 52, 5, 5),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::QuotaNotFound { .. }));
+
+        // Removing a quota keeps the others
+        let remove = |quota_id: &str| ExecuteMsg::RemoveQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota_id: quota_id.to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            remove("daily"),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            remove("daily"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::QuotaNotFound { .. }));
+        let value = quotas(&deps);
+        assert_eq!(value.len(), 2);
+        assert_eq!(value[0].quota.name, "weekly");
+        assert_eq!(value[0].flow.outflow, Uint256::from(30_u32));
+
+        // The path goes away with its last quota
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            remove("weekly"),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info(GOV_ADDR, &[]),
+            remove("monthly"),
+        )
+        .unwrap();
+        let err = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap_err();
+        assert!(matches!(err, StdError::NotFound { .. }));
+    }
+
+    #[test] // Tests the ResetPathQuota message
+    fn management_reset_path_quota() {
+        let mut deps = mock_dependencies();
+        IBCMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(IBC_ADDR))
+            .unwrap();
+        GOVMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(GOV_ADDR))
+            .unwrap();
+
+        let quotas = vec![
+            QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10),
+            QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10),
+        ];
+        for channel_id in ["channel", "any"] {
+            let msg = ExecuteMsg::AddPath {
+                channel_id: channel_id.to_string(),
+                denom: format!("denom"),
+                quotas: quotas.clone(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+        }
+
+        deps.querier.set_supply("denom", 1_000_u32);
+        let send_msg = test_msg_send!(
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            funds: 30_u32.into()
+        );
+        sudo(deps.as_mut(), mock_env(), send_msg).unwrap();
+
+        let reset = |channel_id: &str, quota_id: Option<&str>| ExecuteMsg::ResetPathQuota {
+            channel_id: channel_id.to_string(),
+            denom: format!("denom"),
+            quota_id: quota_id.map(String::from),
+        };
+        let quotas = |deps: &OwnedDeps<_, _, _>, channel_id: &str| -> Vec<RateLimit> {
+            let query_msg = QueryMsg::GetQuotas {
+                channel_id: channel_id.to_string(),
+                denom: format!("denom"),
+            };
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap()
+        };
+
+        // Only governance can reset quotas
+        let msg = reset("channel", Some("daily"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info(IBC_ADDR, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // Unknown quotas are reported
+        let msg = reset("channel", Some("monthly"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::QuotaNotFound { .. }));
+        let msg = reset("other_channel", None);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::QuotaNotFound { .. }));
+
+        // A single quota is reset, and the flow before the reset is reported
+        let msg = reset("channel", Some("daily"));
+        let res = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+        assert!(res
+            .attributes
+            .contains(&Attribute::new("daily_outflow_before", "30")));
+        let value = quotas(&deps, "channel");
+        assert_eq!(value[0].flow.outflow, Uint256::zero());
+        assert_eq!(value[1].flow.outflow, Uint256::from(30_u32));
+
+        // The any path can be reset as a whole
+        let msg = reset("any", None);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+        assert!(res
+            .attributes
+            .contains(&Attribute::new("weekly_outflow_before", "30")));
+        let value = quotas(&deps, "any");
+        assert_eq!(value[0].flow.outflow, Uint256::zero());
+        assert_eq!(value[1].flow.outflow, Uint256::zero());
+    }
+
+    #[test] // Tests that loosening changes are delayed while tightening ones are not
+    fn management_timelock() {
+        let mut deps = mock_dependencies();
+        IBCMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(IBC_ADDR))
+            .unwrap();
+        GOVMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(GOV_ADDR))
+            .unwrap();
+        GUARDIAN
+            .save(deps.as_mut().storage, &Addr::unchecked("guardian"))
+            .unwrap();
+
+        let env = mock_env();
+        let msg = ExecuteMsg::AddPath {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quotas: vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+        // Only governance can set the timelock
+        let msg = ExecuteMsg::SetTimelock { delay: 3600 };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(IBC_ADDR, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+        let update = |send_recv: u32| ExecuteMsg::UpdateQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota: QuotaMsg::new("weekly", RESET_TIME_WEEKLY, send_recv, send_recv),
+        };
+        let query_quotas = |deps: &OwnedDeps<_, _, _>| {
+            let query_msg = QueryMsg::GetQuotas {
+                channel_id: format!("channel"),
+                denom: format!("denom"),
+            };
+            let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+            let value: Vec<RateLimit> = from_binary(&res).unwrap();
+            value[0].quota.max_percentage_send
+        };
+
+        // Tightening the quota is applied right away
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            update(5),
+        )
+        .unwrap();
+        assert_eq!(query_quotas(&deps), Some(Decimal::percent(5 * 100)));
+
+        // Loosening it is queued
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            update(20),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[1], Attribute::new("id", "0"));
+        assert_eq!(query_quotas(&deps), Some(Decimal::percent(5 * 100)));
+
+        let query_msg = QueryMsg::GetPendingChanges {
+            start_after: None,
+            limit: None,
+        };
+        let res = query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap();
+        let value: Vec<PendingChange> = from_binary(&res).unwrap();
+        assert_eq!(
+            value,
+            vec![PendingChange {
+                id: 0,
+                proposer: Addr::unchecked(GOV_ADDR),
+                msg: update(20),
+                executable_at: env.block.time.plus_seconds(3600),
+            }]
+        );
+
+        let execute_pending = ExecuteMsg::ExecutePendingChange { id: 0 };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            execute_pending.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::TimelockNotExpired {
+                id: 0,
+                executable_at: env.block.time.plus_seconds(3600),
+            }
+        );
+
+        // Anyone can execute the change once the timelock expires
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(3600);
+        execute(
+            deps.as_mut(),
+            later.clone(),
+            mock_info("anyone", &[]),
+            execute_pending.clone(),
+        )
+        .unwrap();
+        assert_eq!(query_quotas(&deps), Some(Decimal::percent(20 * 100)));
+        let err = execute(
+            deps.as_mut(),
+            later,
+            mock_info("anyone", &[]),
+            execute_pending,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PendingChangeNotFound { id: 0 });
+
+        // Removing the path is queued and the guardian can cancel it
+        let msg = ExecuteMsg::RemovePath {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(IBC_ADDR, &[]), msg).unwrap();
+        assert_eq!(res.attributes[1], Attribute::new("id", "1"));
+
+        let cancel = ExecuteMsg::CancelPendingChange { id: 1 };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(IBC_ADDR, &[]),
+            cancel.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("guardian", &[]),
+            cancel,
+        )
+        .unwrap();
+        let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+        let value: Vec<PendingChange> = from_binary(&res).unwrap();
+        assert!(value.is_empty());
+        assert_eq!(query_quotas(&deps), Some(Decimal::percent(20 * 100)));
+
+        // Reducing the timelock is a loosening change as well
+        let msg = ExecuteMsg::SetTimelock { delay: 0 };
+        execute(deps.as_mut(), env, mock_info(GOV_ADDR, &[]), msg).unwrap();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(value.timelock, 3600);
+
+        // Aligning the periods can end the current one early, so it is queued
+        // even if the quota is otherwise tighter
+        let msg = ExecuteMsg::UpdateQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota: QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 5, 5).aligned(0),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+        assert_eq!(res.attributes[1], Attribute::new("id", "3"));
+        assert_eq!(query_quotas(&deps), Some(Decimal::percent(20 * 100)));
+    }
+
+    #[test] // Tests that resets, exemptions and re-adding a path wait for the timelock
+    fn management_timelock_frees_capacity() {
+        let mut deps = mock_dependencies();
+        IBCMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(IBC_ADDR))
+            .unwrap();
+        GOVMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(GOV_ADDR))
+            .unwrap();
+
+        let env = mock_env();
+        let add_path = ExecuteMsg::AddPath {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quotas: vec![QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)],
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_ADDR, &[]),
+            add_path.clone(),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::SetTimelock { delay: 3600 };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+
+        let is_queued = |deps: &mut OwnedDeps<_, _, _>, msg: ExecuteMsg| {
+            let res = execute(deps.as_mut(), mock_env(), mock_info(GOV_ADDR, &[]), msg).unwrap();
+            res.attributes[0] == Attribute::new("method", "try_queue_change")
+        };
+
+        // Use some of the capacity
+        let msg = test_msg_send!(
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            funds: 300_u32.into()
+        );
+        deps.querier.set_supply("denom", 3_000_u32);
+        sudo(deps.as_mut(), env.clone(), msg).unwrap();
+        let outflow = |deps: &OwnedDeps<_, _, _>| {
+            let query_msg = QueryMsg::GetQuotas {
+                channel_id: format!("channel"),
+                denom: format!("denom"),
+            };
+            let res = query(deps.as_ref(), mock_env(), query_msg).unwrap();
+            let value: Vec<RateLimit> = from_binary(&res).unwrap();
+            value[0].flow.outflow
+        };
+
+        // Adding the same quotas again would reset the flow
+        assert!(is_queued(&mut deps, add_path));
+        let reset = ExecuteMsg::ResetPathQuota {
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            quota_id: Some(format!("weekly")),
+        };
+        assert!(is_queued(&mut deps, reset));
+        assert_eq!(outflow(&deps), Uint256::from(300_u32));
+
+        // Any new exemption stops rejecting the transfers of the address.
+        // Changing it from skip to record only tightens it
+        let exempt = |mode: ExemptionMode| ExecuteMsg::AddExemption {
+            address: format!("alice"),
+            channel_id: None,
+            denom: None,
+            mode,
+        };
+        assert!(is_queued(&mut deps, exempt(ExemptionMode::Record)));
+        assert!(is_queued(&mut deps, exempt(ExemptionMode::Skip)));
+        let mut later = env;
+        later.block.time = later.block.time.plus_seconds(3600);
+        let execute_pending = ExecuteMsg::ExecutePendingChange { id: 3 };
+        execute(
+            deps.as_mut(),
+            later,
+            mock_info("anyone", &[]),
+            execute_pending,
+        )
+        .unwrap();
+        assert!(!is_queued(&mut deps, exempt(ExemptionMode::Record)));
+        assert!(is_queued(&mut deps, exempt(ExemptionMode::Skip)));
+    }
 }
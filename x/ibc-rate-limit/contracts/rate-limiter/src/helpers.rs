@@ -1,11 +1,23 @@
 #![cfg(test)]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
-use cosmwasm_std::{to_binary, Addr, CosmosMsg, StdResult, WasmMsg};
+use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage};
+use cosmwasm_std::{
+    from_slice, to_binary, Addr, CosmosMsg, Decimal, Empty, OwnedDeps, Querier, QuerierResult,
+    QueryRequest, StdResult, SystemError, SystemResult, Uint256, WasmMsg,
+};
+use osmosis_std::types::cosmos::base::v1beta1::Coin;
+use osmosis_std::types::osmosis::twap::v1beta1::{
+    ArithmeticTwapToNowRequest, ArithmeticTwapToNowResponse,
+};
 
 use crate::msg::ExecuteMsg;
 use crate::msg::SudoMsg;
+use crate::packet::{QuerySupplyOfRequest, QuerySupplyOfResponse};
 
 /// CwTemplateContract is a wrapper around Addr that provides a lot of helpers
 /// for working with this.
@@ -30,14 +42,118 @@ impl RateLimitingContract {
     pub fn sudo<T: Into<SudoMsg>>(&self, msg: T) -> cw_multi_test::SudoMsg {
         let msg = to_binary(&msg.into()).unwrap();
         cw_multi_test::SudoMsg::Wasm(cw_multi_test::WasmSudo {
-            contract_addr: self.addr().into(),
+            contract_addr: self.addr(),
             msg,
         })
     }
 }
 
+const SUPPLY_OF_PATH: &str = "/cosmos.bank.v1beta1.Query/SupplyOf";
+const TWAP_TO_NOW_PATH: &str = "/osmosis.twap.v1beta1.Query/ArithmeticTwapToNow";
+
+/// Answers the stargate queries that the contract uses: SupplyOf, for the
+/// channel value, and ArithmeticTwapToNow, for the price of quotas. Neither
+/// the cosmwasm mocks nor cw-multi-test support stargate queries, so the
+/// values are kept here and any other query is passed on to the wrapped
+/// querier. Denoms without a supply report zero, like the bank module, and
+/// pools without a TWAP return an error, like the twap module.
+pub struct StargateQuerier<Q> {
+    base: Q,
+    supplies: HashMap<String, Uint256>,
+    twaps: HashMap<(u64, String, String), Decimal>,
+}
+
+impl<Q> StargateQuerier<Q> {
+    pub fn new(base: Q) -> Self {
+        Self::with_supplies(base, HashMap::new())
+    }
+
+    pub fn with_supplies(base: Q, supplies: HashMap<String, Uint256>) -> Self {
+        StargateQuerier {
+            base,
+            supplies,
+            twaps: HashMap::new(),
+        }
+    }
+
+    pub fn set_supply(&mut self, denom: impl Into<String>, amount: impl Into<Uint256>) {
+        self.supplies.insert(denom.into(), amount.into());
+    }
+
+    /// Sets the price of `base` in `quote` on a pool, regardless of the window
+    pub fn set_twap(&mut self, pool_id: u64, base: &str, quote: &str, price: Decimal) {
+        self.twaps
+            .insert((pool_id, base.to_string(), quote.to_string()), price);
+    }
+
+    pub fn remove_twap(&mut self, pool_id: u64, base: &str, quote: &str) {
+        self.twaps
+            .remove(&(pool_id, base.to_string(), quote.to_string()));
+    }
+}
+
+impl<'a, Q: Deref<Target = dyn Querier + 'a>> Querier for StargateQuerier<Q> {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
+            Ok(request) => request,
+            Err(e) => {
+                return SystemResult::Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {e}"),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        match request {
+            QueryRequest::Stargate { path, data } if path == SUPPLY_OF_PATH => {
+                let denom = match QuerySupplyOfRequest::try_from(data) {
+                    Ok(request) => request.denom,
+                    Err(e) => return SystemResult::Ok(Err(e.to_string()).into()),
+                };
+                let amount = self.supplies.get(&denom).copied().unwrap_or_default();
+                let response = QuerySupplyOfResponse {
+                    amount: Some(Coin {
+                        denom,
+                        amount: amount.to_string(),
+                    }),
+                };
+                SystemResult::Ok(to_binary(&response).into())
+            }
+            QueryRequest::Stargate { path, data } if path == TWAP_TO_NOW_PATH => {
+                let request = match ArithmeticTwapToNowRequest::try_from(data) {
+                    Ok(request) => request,
+                    Err(e) => return SystemResult::Ok(Err(e.to_string()).into()),
+                };
+                let key = (request.pool_id, request.base_asset, request.quote_asset);
+                match self.twaps.get(&key) {
+                    Some(price) => {
+                        let response = ArithmeticTwapToNowResponse {
+                            arithmetic_twap: price.to_string(),
+                        };
+                        SystemResult::Ok(to_binary(&response).into())
+                    }
+                    None => SystemResult::Ok(Err(format!("no twap for pool {}", key.0)).into()),
+                }
+            }
+            _ => self.base.raw_query(bin_request),
+        }
+    }
+}
+
+pub type MockDeps = OwnedDeps<MockStorage, MockApi, StargateQuerier<Box<dyn Querier>>>;
+
+/// Like cosmwasm's mock_dependencies, but the querier answers the stargate
+/// queries of the contract
+pub fn mock_dependencies() -> MockDeps {
+    OwnedDeps {
+        storage: MockStorage::default(),
+        api: MockApi::default(),
+        querier: StargateQuerier::new(Box::new(MockQuerier::default())),
+        custom_query_type: PhantomData,
+    }
+}
+
 pub mod tests {
-    use cosmwasm_std::{Timestamp, Uint256};
+    use cosmwasm_std::{Decimal, Timestamp, Uint256};
 
     use crate::state::RateLimit;
 
@@ -51,8 +167,14 @@ pub mod tests {
         period_end: Timestamp,
     ) {
         assert_eq!(value.quota.name, quota_name);
-        assert_eq!(value.quota.max_percentage_send, send_recv.0);
-        assert_eq!(value.quota.max_percentage_recv, send_recv.1);
+        assert_eq!(
+            value.quota.max_percentage_send,
+            Some(Decimal::from_ratio(send_recv.0, 1_u32))
+        );
+        assert_eq!(
+            value.quota.max_percentage_recv,
+            Some(Decimal::from_ratio(send_recv.1, 1_u32))
+        );
         assert_eq!(value.quota.duration, duration);
         assert_eq!(value.flow.inflow, inflow);
         assert_eq!(value.flow.outflow, outflow);
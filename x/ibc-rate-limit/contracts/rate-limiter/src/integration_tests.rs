@@ -1,20 +1,64 @@
 #![cfg(test)]
-use crate::{helpers::RateLimitingContract, msg::ExecuteMsg, test_msg_send, ContractError};
-use cosmwasm_std::{Addr, Coin, Empty, Timestamp, Uint128, Uint256};
+use crate::{
+    helpers::{RateLimitingContract, StargateQuerier},
+    msg::ExecuteMsg,
+    test_msg_send, ContractError,
+};
+use cosmwasm_std::{
+    Addr, Binary, Coin, Deps, DepsMut, Empty, Env, QuerierWrapper, Response, StdResult, Timestamp,
+    Uint128, Uint256,
+};
 use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
+use std::{cell::RefCell, collections::HashMap};
 
 use crate::{
-    msg::{InstantiateMsg, PathMsg, QuotaMsg},
+    msg::{InstantiateMsg, PathMsg, QueryMsg, QuotaMsg, SudoMsg},
     state::tests::{RESET_TIME_DAILY, RESET_TIME_MONTHLY, RESET_TIME_WEEKLY},
+    state::QuotaKind,
 };
 
+thread_local! {
+    // The supplies reported to the contract. Each test runs on its own thread
+    static SUPPLIES: RefCell<HashMap<String, Uint256>> = RefCell::default();
+}
+
+fn set_supply(denom: impl Into<String>, amount: impl Into<Uint256>) {
+    SUPPLIES.with(|supplies| supplies.borrow_mut().insert(denom.into(), amount.into()));
+}
+
+fn supply_querier(querier: QuerierWrapper) -> StargateQuerier<QuerierWrapper> {
+    StargateQuerier::with_supplies(querier, SUPPLIES.with(|supplies| supplies.borrow().clone()))
+}
+
+// cw-multi-test can't answer stargate queries, so the entry points that obtain
+// the channel value are given a querier that serves the supplies from above
+fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    let querier = supply_querier(deps.querier);
+    let deps = DepsMut {
+        storage: deps.storage,
+        api: deps.api,
+        querier: QuerierWrapper::new(&querier),
+    };
+    crate::contract::sudo(deps, env, msg)
+}
+
+fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    let querier = supply_querier(deps.querier);
+    let deps = Deps {
+        storage: deps.storage,
+        api: deps.api,
+        querier: QuerierWrapper::new(&querier),
+    };
+    crate::contract::query(deps, env, msg)
+}
+
 pub fn contract_template() -> Box<dyn Contract<Empty>> {
     let contract = ContractWrapper::new(
         crate::contract::execute,
         crate::contract::instantiate,
-        crate::contract::query,
+        query,
     )
-    .with_sudo(crate::contract::sudo);
+    .with_sudo(sudo);
     Box::new(contract)
 }
 
@@ -47,6 +91,7 @@ fn proper_instantiate(paths: Vec<PathMsg>) -> (App, RateLimitingContract) {
     let msg = InstantiateMsg {
         gov_module: Addr::unchecked(GOV_ADDR),
         ibc_module: Addr::unchecked(IBC_ADDR),
+        guardian: None,
         paths,
     };
 
@@ -77,12 +122,15 @@ fn expiration() {
         denom: format!("denom"),
         quotas: vec![quota],
     }]);
+    let msg = ExecuteMsg::SetVerboseResponses { enabled: true };
+    let cosmos_msg = cw_rate_limit_contract.call(msg).unwrap();
+    app.execute(Addr::unchecked(GOV_ADDR), cosmos_msg).unwrap();
 
     // Using all the allowance
+    set_supply("denom", 3_000_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 300_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -105,7 +153,6 @@ fn expiration() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 300_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -134,7 +181,6 @@ fn expiration() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 300_u32.into()
     );
 
@@ -170,10 +216,10 @@ fn multiple_quotas() {
     }]);
 
     // Sending 1% to use the daily allowance
+    set_supply("denom", 101_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -183,7 +229,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -199,7 +244,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
 
@@ -218,7 +262,6 @@ fn multiple_quotas() {
         let msg = test_msg_send!(
             channel_id: format!("channel"),
             denom: format!("denom"),
-            channel_value: 101_u32.into(),
             funds: 1_u32.into()
         );
         let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -235,7 +278,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -251,7 +293,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -268,7 +309,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -283,7 +323,6 @@ fn multiple_quotas() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 101_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -304,10 +343,10 @@ fn channel_value_cached() {
     }]);
 
     // Sending 1% (half of the daily allowance)
+    set_supply("denom", 100_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 100_u32.into(),
         funds: 1_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -317,17 +356,16 @@ fn channel_value_cached() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 100_u32.into(),
         funds: 3_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
     app.sudo(cosmos_msg).unwrap_err();
 
     // Even if the channel value increases, the percentage is calculated based on the value at period start
+    set_supply("denom", 100000_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 100000_u32.into(),
         funds: 3_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
@@ -344,10 +382,10 @@ fn channel_value_cached() {
     // Sending 1% of a new value (10_000) passes the daily check, cause it
     // has expired, but not the weekly check (The value for last week is
     // sitll 100, as only 1 day has passed)
+    set_supply("denom", 10_000_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 10_000_u32.into(),
         funds: 100_u32.into()
     );
 
@@ -364,7 +402,6 @@ fn channel_value_cached() {
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 10_000_u32.into(),
         funds: 100_u32.into()
     );
 
@@ -372,10 +409,10 @@ fn channel_value_cached() {
     app.sudo(cosmos_msg).unwrap();
 
     // If the value magically decreasses. We can still send up to 100 more (1% of 10k)
+    set_supply("denom", 1_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 1_u32.into(),
         funds: 75_u32.into()
     );
 
@@ -388,10 +425,10 @@ fn add_paths_later() {
     let (mut app, cw_rate_limit_contract) = proper_instantiate(vec![]);
 
     // All sends are allowed
+    set_supply("denom", 3_000_u32);
     let msg = test_msg_send!(
         channel_id: format!("channel"),
         denom: format!("denom"),
-        channel_value: 3_000_u32.into(),
         funds: 300_u32.into()
     );
     let cosmos_msg = cw_rate_limit_contract.sudo(msg.clone());
@@ -415,3 +452,58 @@ fn add_paths_later() {
     let cosmos_msg = cw_rate_limit_contract.sudo(msg);
     app.sudo(cosmos_msg).unwrap_err();
 }
+
+#[test] // Tests that sliding windows don't allow a full quota on each side of a period boundary
+fn sliding_window_boundary() {
+    let fixed = QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10);
+    let sliding = QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10)
+        .with_kind(QuotaKind::SlidingWindow { buckets: 24 });
+
+    let (mut app, cw_rate_limit_contract) = proper_instantiate(vec![
+        PathMsg::new("any", "fixed", vec![fixed]),
+        PathMsg::new("any", "sliding", vec![sliding]),
+    ]);
+
+    set_supply("fixed", 3_000_u32);
+    set_supply("sliding", 3_000_u32);
+    let send = |app: &mut App, denom: &str, funds: u32| {
+        let msg = test_msg_send!(
+            channel_id: format!("channel"),
+            denom: denom.to_string(),
+            funds: funds.into()
+        );
+        app.sudo(cw_rate_limit_contract.sudo(msg))
+    };
+
+    // Both quotas start being tracked with a small transfer
+    for denom in ["fixed", "sliding"] {
+        send(&mut app, denom, 1).unwrap();
+    }
+
+    // ... Almost one day passes. The rest of the quota is used right before the period ends
+    app.update_block(|b| {
+        b.height += 10;
+        b.time = b.time.plus_seconds(RESET_TIME_DAILY - 1)
+    });
+    for denom in ["fixed", "sliding"] {
+        send(&mut app, denom, 299).unwrap();
+    }
+
+    // ... Two seconds later, the fixed period has been reset but the sliding
+    // window still remembers the transfers made during the last hour
+    app.update_block(|b| {
+        b.height += 1;
+        b.time = b.time.plus_seconds(2)
+    });
+    send(&mut app, "fixed", 300).unwrap();
+    send(&mut app, "sliding", 300).unwrap_err();
+    // Only the value of the first bucket has left the window
+    send(&mut app, "sliding", 1).unwrap();
+
+    // ... After a full day since the big transfer, the sliding window has capacity again
+    app.update_block(|b| {
+        b.height += 10;
+        b.time = b.time.plus_seconds(RESET_TIME_DAILY)
+    });
+    send(&mut app, "sliding", 299).unwrap();
+}
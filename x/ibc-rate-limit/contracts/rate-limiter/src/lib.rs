@@ -1,4 +1,6 @@
 #![allow(clippy::result_large_err)]
+// The tests build most of their strings with format!() and vec![]
+#![cfg_attr(test, allow(clippy::useless_format, clippy::useless_vec))]
 
 // Contract
 pub mod contract;
@@ -8,8 +10,11 @@ mod state;
 
 pub mod packet;
 
+mod channel_value;
+
 // Functions
 mod execute;
+mod migrations;
 mod query;
 mod sudo;
 
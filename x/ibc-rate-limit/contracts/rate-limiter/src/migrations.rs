@@ -0,0 +1,382 @@
+use cosmwasm_std::{Addr, Decimal, DepsMut, Empty, Order, Response, StdResult, Storage};
+use cw2::{get_contract_version, set_contract_version};
+
+use crate::state::{
+    Flow, Quota, QuotaKind, RateLimit, GOVMODULE, IBCMODULE, PATHS_BY_DENOM, RATE_LIMIT_TRACKERS,
+};
+use crate::ContractError;
+
+type Migration = fn(&mut dyn Storage) -> Result<(), ContractError>;
+
+/// State transformations needed to reach each contract version, in the order
+/// in which they have to be applied. A migration only runs if the version
+/// stored by the deployed contract is lower than the version it upgrades to.
+const MIGRATIONS: &[(&str, Migration)] = &[("0.2.0", migrate_v0_2_0)];
+
+/// Upgrades the stored state from the version recorded in cw2 to the current
+/// contract version and, optionally, replaces the module addresses.
+pub fn upgrade(
+    deps: DepsMut,
+    contract_name: &str,
+    contract_version: &str,
+    gov_module: Option<Addr>,
+    ibc_module: Option<Addr>,
+) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != contract_name {
+        return Err(ContractError::InvalidMigrationContract {
+            previous_contract: stored.contract,
+            contract: contract_name.to_string(),
+        });
+    }
+
+    let invalid_version = || ContractError::InvalidMigrationVersion {
+        previous_version: stored.version.clone(),
+        version: contract_version.to_string(),
+    };
+    let previous = parse_version(&stored.version).ok_or_else(invalid_version)?;
+    let current = parse_version(contract_version).ok_or_else(invalid_version)?;
+    // Downgrades are not supported
+    if previous > current {
+        return Err(invalid_version());
+    }
+
+    let mut response = Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("previous_version", &stored.version)
+        .add_attribute("version", contract_version);
+
+    for (version, migration) in MIGRATIONS {
+        let target = parse_version(version).ok_or_else(invalid_version)?;
+        if previous < target && target <= current {
+            migration(deps.storage)?;
+            response = response.add_attribute("migration", *version);
+        }
+    }
+
+    if let Some(gov_module) = gov_module {
+        GOVMODULE.save(deps.storage, &gov_module)?;
+        response = response.add_attribute("gov_module", gov_module);
+    }
+    if let Some(ibc_module) = ibc_module {
+        IBCMODULE.save(deps.storage, &ibc_module)?;
+        response = response.add_attribute("ibc_module", ibc_module);
+    }
+
+    set_contract_version(deps.storage, contract_name, contract_version)?;
+    Ok(response)
+}
+
+/// Parses a "major.minor.patch" version. Pre-release and build suffixes are
+/// ignored, so "0.2.0-rc1" is considered the same version as "0.2.0"
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|part| part.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    match parts.next() {
+        Some(_) => None,
+        None => Some(version),
+    }
+}
+
+/// Storage layout of the 0.1.0 release. These types should not change, as
+/// they are only used to read the state written by that version.
+mod v0_1_0 {
+    use cosmwasm_std::{Timestamp, Uint256};
+    use cw_storage_plus::Map;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct Quota {
+        pub name: String,
+        pub max_percentage_send: u32,
+        pub max_percentage_recv: u32,
+        pub duration: u64,
+        pub channel_value: Option<Uint256>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct Flow {
+        pub inflow: Uint256,
+        pub outflow: Uint256,
+        pub period_end: Timestamp,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct RateLimit {
+        pub quota: Quota,
+        pub flow: Flow,
+    }
+
+    pub const RATE_LIMIT_TRACKERS: Map<(String, String), Vec<RateLimit>> = Map::new("flow");
+}
+
+impl From<v0_1_0::RateLimit> for RateLimit {
+    fn from(legacy: v0_1_0::RateLimit) -> Self {
+        RateLimit {
+            quota: Quota {
+                name: legacy.quota.name,
+                max_percentage_send: Some(Decimal::from_ratio(
+                    legacy.quota.max_percentage_send,
+                    1_u32,
+                )),
+                max_percentage_recv: Some(Decimal::from_ratio(
+                    legacy.quota.max_percentage_recv,
+                    1_u32,
+                )),
+                max_amount_send: None,
+                max_amount_recv: None,
+                duration: legacy.quota.duration,
+                channel_value: legacy.quota.channel_value,
+                kind: QuotaKind::Fixed,
+                alignment: None,
+                gross: false,
+                price: None,
+                last_price: None,
+            },
+            flow: Flow {
+                inflow: legacy.flow.inflow,
+                outflow: legacy.flow.outflow,
+                period_end: legacy.flow.period_end,
+                // 0.1.0 periods weren't aligned, so they lasted the whole duration
+                period_start: Some(legacy.flow.period_end.minus_seconds(legacy.quota.duration)),
+            },
+            buckets: vec![],
+            last_refill: None,
+        }
+    }
+}
+
+/// Rewrites every tracker stored by 0.1.0 in the current format, so that any
+/// field introduced since then is explicitly set. Whole percentages are
+/// converted to decimals. The index of paths by denom is also built.
+///
+/// 0.1.0 stored an empty list of trackers for the "any" channel (and for the
+/// exact path) of every denom that was transferred through a path with quotas.
+/// Those entries are removed instead.
+///
+/// The sends made by 0.1.0 have no record in SENT_PACKETS, and none is created
+/// here. Undoing them falls back to the current period of the path quotas (see
+/// FIRST_RECORDED_SEQUENCE).
+fn migrate_v0_2_0(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let legacy_trackers = v0_1_0::RATE_LIMIT_TRACKERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for (key, rate_limits) in legacy_trackers {
+        if rate_limits.is_empty() {
+            RATE_LIMIT_TRACKERS.remove(storage, key);
+            continue;
+        }
+        let rate_limits: Vec<RateLimit> = rate_limits.into_iter().map(Into::into).collect();
+        PATHS_BY_DENOM.save(storage, (key.1.clone(), key.0.clone()), &Empty {})?;
+        RATE_LIMIT_TRACKERS.save(storage, key, &rate_limits)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::mock_env;
+    use cosmwasm_std::{Addr, Attribute, Decimal, Timestamp, Uint256};
+    use cw2::{get_contract_version, set_contract_version};
+
+    use super::parse_version;
+    use crate::contract::{migrate, sudo};
+    use crate::helpers::{mock_dependencies, MockDeps};
+    use crate::msg::{MigrateMsg, SudoMsg};
+    use crate::packet::Packet;
+    use crate::state::{GOVMODULE, IBCMODULE, PATHS_BY_DENOM, RATE_LIMIT_TRACKERS};
+    use crate::{test_msg_send, ContractError};
+
+    const IBC_ADDR: &str = "IBC_MODULE";
+    const GOV_ADDR: &str = "GOV_MODULE";
+    const CONTRACT_NAME: &str = "crates.io:rate-limiter";
+
+    // A weekly quota on the "any" channel, stored by 0.1.0 after a send of 300
+    const V0_1_0_TRACKERS: &str = r#"[{"quota":{"name":"weekly","max_percentage_send":10,"max_percentage_recv":10,"duration":604800,"channel_value":"3300"},"flow":{"inflow":"0","outflow":"300","period_end":"1572402219879305533"}}]"#;
+
+    fn v0_1_0_deps() -> MockDeps {
+        let mut deps = mock_dependencies();
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+        IBCMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(IBC_ADDR))
+            .unwrap();
+        GOVMODULE
+            .save(deps.as_mut().storage, &Addr::unchecked(GOV_ADDR))
+            .unwrap();
+        let key = RATE_LIMIT_TRACKERS.key(("any".to_string(), "denom".to_string()));
+        deps.as_mut().storage.set(&key, V0_1_0_TRACKERS.as_bytes());
+        // The send also stored an empty list for the channel it went through
+        let key = RATE_LIMIT_TRACKERS.key(("channel".to_string(), "denom".to_string()));
+        deps.as_mut().storage.set(&key, b"[]");
+        deps
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(parse_version("0.1.0"), Some((0, 1, 0)));
+        assert_eq!(parse_version("1.12.3-rc1"), Some((1, 12, 3)));
+        assert_eq!(parse_version("1.2"), None);
+        assert_eq!(parse_version("1.2.3.4"), None);
+        assert_eq!(parse_version("one.2.3"), None);
+        assert!(parse_version("0.1.0") < parse_version("0.2.0"));
+        assert!(parse_version("0.10.0") > parse_version("0.9.1"));
+    }
+
+    #[test] // Tests that the state stored by 0.1.0 is kept and usable after migrating
+    fn migrate_from_v0_1_0() {
+        let mut deps = v0_1_0_deps();
+
+        let msg = MigrateMsg::Upgrade {
+            gov_module: None,
+            ibc_module: None,
+        };
+        let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "migration" && attr.value == "0.2.0"));
+
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.contract, CONTRACT_NAME);
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+
+        // The modules are unchanged
+        assert_eq!(IBCMODULE.load(&deps.storage).unwrap(), IBC_ADDR);
+        assert_eq!(GOVMODULE.load(&deps.storage).unwrap(), GOV_ADDR);
+
+        let trackers = RATE_LIMIT_TRACKERS
+            .load(&deps.storage, ("any".to_string(), "denom".to_string()))
+            .unwrap();
+        assert_eq!(trackers.len(), 1);
+        assert_eq!(trackers[0].quota.name, "weekly");
+        assert_eq!(
+            trackers[0].quota.max_percentage_send,
+            Some(Decimal::percent(1000))
+        );
+        assert_eq!(
+            trackers[0].quota.max_percentage_recv,
+            Some(Decimal::percent(1000))
+        );
+        assert_eq!(trackers[0].quota.max_amount_send, None);
+        assert_eq!(trackers[0].quota.max_amount_recv, None);
+        assert_eq!(trackers[0].quota.duration, 604800);
+        assert_eq!(
+            trackers[0].quota.channel_value,
+            Some(Uint256::from(3_300_u32))
+        );
+        assert!(PATHS_BY_DENOM.has(&deps.storage, ("denom".to_string(), "any".to_string())));
+        // Empty lists are removed rather than migrated
+        let channel_path = ("channel".to_string(), "denom".to_string());
+        assert!(!RATE_LIMIT_TRACKERS.has(&deps.storage, channel_path));
+        assert!(!PATHS_BY_DENOM.has(&deps.storage, ("denom".to_string(), "channel".to_string())));
+        assert_eq!(trackers[0].flow.inflow, Uint256::from(0_u32));
+        assert_eq!(trackers[0].flow.outflow, Uint256::from(300_u32));
+        assert_eq!(
+            trackers[0].flow.period_end,
+            Timestamp::from_nanos(1572402219879305533)
+        );
+
+        // The flow stored before the migration is still enforced
+        deps.querier.set_supply("denom", 3_300_u32);
+        let msg = test_msg_send!(
+            channel_id: format!("channel"),
+            denom: format!("denom"),
+            funds: 300_u32.into()
+        );
+        let err = sudo(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+        // Sends from before the migration have no record. They are undone from
+        // the current period, like 0.1.0 did
+        let packet = |sequence: u64| {
+            Packet::mock(
+                format!("channel"),
+                format!("channel"),
+                format!("denom"),
+                300_u32.into(),
+            )
+            .with_sequence(sequence)
+        };
+        let undo = |sequence: u64| SudoMsg::UndoSend {
+            packet: packet(sequence),
+        };
+        let outflow = |deps: &MockDeps| {
+            let key = ("any".to_string(), "denom".to_string());
+            RATE_LIMIT_TRACKERS.load(&deps.storage, key).unwrap()[0]
+                .flow
+                .outflow
+        };
+        let res = sudo(deps.as_mut(), mock_env(), undo(3)).unwrap();
+        assert!(res
+            .attributes
+            .contains(&Attribute::new("recorded", "false")));
+        assert_eq!(outflow(&deps), Uint256::zero());
+
+        // Once sends are recorded, only the ones with an older sequence than
+        // the first recorded send are undone without a record
+        let msg = SudoMsg::SendPacket { packet: packet(5) };
+        sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(outflow(&deps), Uint256::from(300_u32));
+        sudo(deps.as_mut(), mock_env(), undo(6)).unwrap();
+        assert_eq!(outflow(&deps), Uint256::from(300_u32));
+        sudo(deps.as_mut(), mock_env(), undo(4)).unwrap();
+        assert_eq!(outflow(&deps), Uint256::zero());
+
+        // Migrating again is a noop
+        let msg = MigrateMsg::Upgrade {
+            gov_module: None,
+            ibc_module: None,
+        };
+        let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(!res.attributes.iter().any(|attr| attr.key == "migration"));
+    }
+
+    #[test] // Tests that the module addresses can be replaced when migrating
+    fn migrate_replacing_modules() {
+        let mut deps = v0_1_0_deps();
+
+        let msg = MigrateMsg::Upgrade {
+            gov_module: Some(Addr::unchecked("NEW_GOV_MODULE")),
+            ibc_module: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(IBCMODULE.load(&deps.storage).unwrap(), IBC_ADDR);
+        assert_eq!(GOVMODULE.load(&deps.storage).unwrap(), "NEW_GOV_MODULE");
+
+        let msg = MigrateMsg::Upgrade {
+            gov_module: None,
+            ibc_module: Some(Addr::unchecked("NEW_IBC_MODULE")),
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(IBCMODULE.load(&deps.storage).unwrap(), "NEW_IBC_MODULE");
+        assert_eq!(GOVMODULE.load(&deps.storage).unwrap(), "NEW_GOV_MODULE");
+    }
+
+    #[test] // Tests that we can't migrate from other contracts or newer versions
+    fn invalid_migrations() {
+        let msg = MigrateMsg::Upgrade {
+            gov_module: None,
+            ibc_module: None,
+        };
+
+        let mut deps = v0_1_0_deps();
+        set_contract_version(deps.as_mut().storage, "crates.io:other", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidMigrationContract { .. }
+        ));
+
+        let mut deps = v0_1_0_deps();
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigrationVersion { .. }));
+
+        let mut deps = v0_1_0_deps();
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "latest").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigrationVersion { .. }));
+    }
+}
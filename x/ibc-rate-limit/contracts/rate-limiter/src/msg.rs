@@ -1,12 +1,14 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Addr;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
-#[cfg(test)]
-use cosmwasm_std::Uint256;
+use cosmwasm_std::{Addr, Decimal, Timestamp, Uint256};
+use schemars::JsonSchema;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::packet::Packet;
+use crate::state::{ChannelValueSource, ExemptionMode, FlowType, Path, QuotaKind, QuotaPrice};
 
 // PathMsg contains a channel_id and denom to represent a unique identifier within ibc-go, and a list of rate limit quotas
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -30,12 +32,132 @@ impl PathMsg {
     }
 }
 
+/// A percentage of the channel value. It can be expressed as a whole number
+/// (i.e.: 5 for 5%) or, when more precision is needed, as a decimal string
+/// (i.e.: "0.25" for 0.25%)
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum Percentage {
+    Whole(u32),
+    Decimal(Decimal),
+}
+
+// Deserialized by hand because the derived untagged deserializer buffers the
+// input in a way that pulls float operations into the wasm, which the chain
+// rejects. For the same reason, errors don't go through serde::de::Unexpected
+impl<'de> Deserialize<'de> for Percentage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(PercentageVisitor)
+    }
+}
+
+struct PercentageVisitor;
+
+impl PercentageVisitor {
+    fn invalid<E: de::Error>(value: impl fmt::Display) -> E {
+        E::custom(format!(
+            "invalid percentage {value}, expected a whole percentage or a decimal string"
+        ))
+    }
+}
+
+impl<'de> Visitor<'de> for PercentageVisitor {
+    type Value = Percentage;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a whole percentage or a decimal string")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        u32::try_from(v)
+            .map(Percentage::Whole)
+            .map_err(|_| Self::invalid(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        match u64::try_from(v) {
+            Ok(v) => self.visit_u64(v),
+            Err(_) => Err(Self::invalid(v)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Decimal::from_str(v)
+            .map(Percentage::Decimal)
+            .map_err(|_| Self::invalid(format!("\"{v}\"")))
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Err(Self::invalid(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Err(Self::invalid("null"))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, _seq: A) -> Result<Self::Value, A::Error> {
+        Err(Self::invalid("list"))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, _map: A) -> Result<Self::Value, A::Error> {
+        Err(Self::invalid("map"))
+    }
+}
+
+impl From<u32> for Percentage {
+    fn from(percentage: u32) -> Self {
+        Percentage::Whole(percentage)
+    }
+}
+
+impl From<Decimal> for Percentage {
+    fn from(percentage: Decimal) -> Self {
+        Percentage::Decimal(percentage)
+    }
+}
+
+impl From<Percentage> for Decimal {
+    fn from(percentage: Percentage) -> Self {
+        match percentage {
+            Percentage::Whole(percentage) => Decimal::from_ratio(percentage, 1_u32),
+            Percentage::Decimal(percentage) => percentage,
+        }
+    }
+}
+
 // QuotaMsg represents a rate limiting Quota when sent as a wasm msg
+//
+// send_recv limits the transfers to a percentage of the channel value, and
+// send_recv_amounts to an absolute amount of the denom. At least one of them
+// should be set. If both are set, the tighter limit applies.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct QuotaMsg {
     pub name: String,
     pub duration: u64,
-    pub send_recv: (u32, u32),
+    #[serde(default)]
+    pub send_recv: Option<(Percentage, Percentage)>,
+    #[serde(default)]
+    pub send_recv_amounts: Option<(Uint256, Uint256)>,
+    /// How the flow is tracked over time. Defaults to fixed periods
+    #[serde(default)]
+    pub kind: QuotaKind,
+    /// Aligns fixed periods to boundaries every `duration` seconds from the
+    /// Unix epoch, shifted by this number of seconds (i.e.: a daily quota
+    /// aligned with 0 resets at midnight UTC). If not set, a period starts
+    /// with the first transfer after the previous one ended
+    #[serde(default)]
+    pub alignment: Option<u64>,
+    /// Checks inflows and outflows independently instead of netting them
+    #[serde(default)]
+    pub gross: bool,
+    /// Converts the transfers to a reference denom with the TWAP of a pool.
+    /// send_recv_amounts are then in the reference denom, and send_recv can't
+    /// be set
+    #[serde(default)]
+    pub price: Option<QuotaPrice>,
 }
 
 impl QuotaMsg {
@@ -43,9 +165,66 @@ impl QuotaMsg {
         QuotaMsg {
             name: name.to_string(),
             duration: seconds,
-            send_recv: (send_percentage, recv_percentage),
+            send_recv: Some((send_percentage.into(), recv_percentage.into())),
+            send_recv_amounts: None,
+            kind: QuotaKind::Fixed,
+            alignment: None,
+            gross: false,
+            price: None,
+        }
+    }
+
+    /// Creates a quota that only limits the absolute amount transferred
+    pub fn absolute(
+        name: &str,
+        seconds: u64,
+        send_amount: impl Into<Uint256>,
+        recv_amount: impl Into<Uint256>,
+    ) -> Self {
+        QuotaMsg {
+            name: name.to_string(),
+            duration: seconds,
+            send_recv: None,
+            send_recv_amounts: Some((send_amount.into(), recv_amount.into())),
+            kind: QuotaKind::Fixed,
+            alignment: None,
+            gross: false,
+            price: None,
         }
     }
+
+    pub fn with_amounts(
+        mut self,
+        send_amount: impl Into<Uint256>,
+        recv_amount: impl Into<Uint256>,
+    ) -> Self {
+        self.send_recv_amounts = Some((send_amount.into(), recv_amount.into()));
+        self
+    }
+
+    pub fn with_kind(mut self, kind: QuotaKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn aligned(mut self, offset: u64) -> Self {
+        self.alignment = Some(offset);
+        self
+    }
+
+    pub fn gross(mut self) -> Self {
+        self.gross = true;
+        self
+    }
+
+    pub fn priced_in(mut self, reference_denom: &str, pool_id: u64, window: u64) -> Self {
+        self.price = Some(QuotaPrice {
+            reference_denom: reference_denom.to_string(),
+            pool_id,
+            window,
+        });
+        self
+    }
 }
 
 /// Initialize the contract with the address of the IBC module and any existing channels.
@@ -54,9 +233,47 @@ impl QuotaMsg {
 pub struct InstantiateMsg {
     pub gov_module: Addr,
     pub ibc_module: Addr,
+    /// The guardian can pause traffic in an emergency
+    pub guardian: Option<Addr>,
     pub paths: Vec<PathMsg>,
 }
 
+/// The traffic affected by a pause
+#[cw_serde]
+pub enum PauseScope {
+    Channel { channel_id: String },
+    Denom { denom: String },
+    Path { channel_id: String, denom: String },
+    All {},
+}
+
+impl From<PauseScope> for Path {
+    fn from(scope: PauseScope) -> Self {
+        match scope {
+            PauseScope::Channel { channel_id } => Path::new(channel_id, "any"),
+            PauseScope::Denom { denom } => Path::new("any", denom),
+            PauseScope::Path { channel_id, denom } => Path::new(channel_id, denom),
+            PauseScope::All {} => Path::new("any", "any"),
+        }
+    }
+}
+
+/// The roles that can be handed over to a different address
+#[cw_serde]
+pub enum AdminRole {
+    GovModule,
+    IbcModule,
+}
+
+impl std::fmt::Display for AdminRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminRole::GovModule => write!(f, "gov_module"),
+            AdminRole::IbcModule => write!(f, "ibc_module"),
+        }
+    }
+}
+
 /// The caller (IBC module) is responsible for correctly calculating the funds
 /// being sent through the channel
 #[cw_serde]
@@ -70,11 +287,118 @@ pub enum ExecuteMsg {
         channel_id: String,
         denom: String,
     },
+    /// Resets the flow of a quota. All the quotas of the path are reset if no
+    /// quota_id is provided
     ResetPathQuota {
+        channel_id: String,
+        denom: String,
+        quota_id: Option<String>,
+    },
+    /// Adds a quota to a path without modifying the flow of its other quotas
+    AddQuota {
+        channel_id: String,
+        denom: String,
+        quota: QuotaMsg,
+    },
+    /// Replaces the quota with the same name. The flow of the current period
+    /// is kept unless the kind of quota changes
+    UpdateQuota {
+        channel_id: String,
+        denom: String,
+        quota: QuotaMsg,
+    },
+    /// Removes a single quota from a path. The path is removed along with its
+    /// last quota
+    RemoveQuota {
         channel_id: String,
         denom: String,
         quota_id: String,
     },
+    /// Sets the quotas that apply to each address individually when
+    /// transferring through the path. An empty list removes them.
+    SetAddressQuotas {
+        channel_id: String,
+        denom: String,
+        quotas: Vec<QuotaMsg>,
+    },
+    /// Exempts an address from rate limiting on a path. Leaving the channel or
+    /// the denom empty exempts the address on all of them.
+    AddExemption {
+        address: String,
+        channel_id: Option<String>,
+        denom: Option<String>,
+        mode: ExemptionMode,
+    },
+    RemoveExemption {
+        address: String,
+        channel_id: Option<String>,
+        denom: Option<String>,
+    },
+    /// Rejects all transfers in the scope until it is unpaused. Can be called
+    /// by the guardian or the governance module
+    Pause {
+        scope: PauseScope,
+    },
+    Unpause {
+        scope: PauseScope,
+    },
+    /// Replaces (or removes) the guardian. Only governance can do this
+    SetGuardian {
+        guardian: Option<Addr>,
+    },
+    /// Proposes a new address for a role. Only governance can do this, and
+    /// the change doesn't take effect until the address accepts it. A new
+    /// proposal replaces the pending one.
+    ProposeAdmin {
+        role: AdminRole,
+        address: Addr,
+    },
+    /// Accepts a role previously proposed to the sender
+    AcceptAdmin {
+        role: AdminRole,
+    },
+    /// Sets how many seconds changes that loosen the rate limits have to wait
+    /// before they can be executed. Only governance can do this
+    SetTimelock {
+        delay: u64,
+    },
+    /// Executes a queued change once its timelock has expired. Anyone can do
+    /// this
+    ExecutePendingChange {
+        id: u64,
+    },
+    /// Discards a queued change. Can be done by the guardian or the
+    /// governance module
+    CancelPendingChange {
+        id: u64,
+    },
+    /// Sets where the channel value of a path comes from. The channel can be
+    /// "any" to set it for all the paths of a denom. Only governance can do this
+    SetChannelValueSource {
+        channel_id: String,
+        denom: String,
+        source: ChannelValueSource,
+    },
+    /// Sets the quotas that cap the combined value of all the denoms
+    /// transferred through a channel. The value of a transfer is its amount
+    /// multiplied by the weight of the denom, and denoms without a weight are
+    /// not counted. These quotas can only limit absolute amounts of that
+    /// value. An empty list removes them.
+    SetChannelQuotas {
+        channel_id: String,
+        quotas: Vec<QuotaMsg>,
+    },
+    /// Sets (or, if empty, removes) the weight of a denom for the channel
+    /// quotas. Only governance can do this
+    SetDenomWeight {
+        denom: String,
+        weight: Option<Decimal>,
+    },
+    /// Sets whether the responses of transfers include the usage of every
+    /// quota. Only governance can do this
+    SetVerboseResponses {
+        enabled: bool,
+    },
 }
 
 #[cw_serde]
@@ -82,24 +406,162 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     #[returns(Vec<crate::state::RateLimit>)]
     GetQuotas { channel_id: String, denom: String },
+    /// Lists the configured paths and their quotas, ordered by channel and
+    /// denom. Use the last path returned as `start_after` to get the next page
+    #[returns(Vec<PathRateLimits>)]
+    ListPaths {
+        start_after: Option<(String, String)>,
+        limit: Option<u32>,
+    },
+    /// Lists the paths of a channel, ordered by denom
+    #[returns(Vec<PathRateLimits>)]
+    ListPathsForChannel {
+        channel_id: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Lists the paths of a denom, ordered by channel
+    #[returns(Vec<PathRateLimits>)]
+    ListPathsForDenom {
+        denom: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Returns the remaining capacity of the quotas of a path and of the "any"
+    /// channel for its denom
+    #[returns(Vec<RemainingCapacity>)]
+    GetRemainingCapacity { channel_id: String, denom: String },
+    #[returns(Vec<crate::state::RateLimit>)]
+    GetAddressQuotas {
+        channel_id: String,
+        denom: String,
+        address: String,
+    },
+    /// Checks whether a transfer would be allowed at the current block time
+    /// without modifying the state. If the address is provided, its
+    /// exemptions and address quotas are also taken into account.
+    #[returns(SimulateTransferResponse)]
+    SimulateTransfer {
+        channel_id: String,
+        denom: String,
+        amount: Uint256,
+        direction: FlowType,
+        address: Option<String>,
+    },
+    #[returns(Config)]
+    GetConfig {},
+    /// Returns the source of the channel value that applies to a path
+    #[returns(ChannelValueSource)]
+    GetChannelValueSource { channel_id: String, denom: String },
+    /// Returns the quotas that apply to all the denoms of a channel combined
+    #[returns(Vec<crate::state::RateLimit>)]
+    GetChannelQuotas { channel_id: String },
+    #[returns(Option<Decimal>)]
+    GetDenomWeight { denom: String },
+    #[returns(Vec<crate::state::PendingChange>)]
+    GetPendingChanges {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(bool)]
+    IsPaused { channel_id: String, denom: String },
+    #[returns(Option<crate::state::ExemptionMode>)]
+    GetExemption {
+        address: String,
+        channel_id: String,
+        denom: String,
+    },
 }
 
 #[cw_serde]
-pub enum SudoMsg {
-    SendPacket {
-        packet: Packet,
-        #[cfg(test)]
-        channel_value_mock: Option<Uint256>,
+pub struct PathRateLimits {
+    pub channel_id: String,
+    pub denom: String,
+    pub rate_limits: Vec<crate::state::RateLimit>,
+}
+
+#[cw_serde]
+pub struct Config {
+    pub gov_module: Addr,
+    pub ibc_module: Addr,
+    pub guardian: Option<Addr>,
+    /// Seconds that changes loosening the rate limits are delayed
+    pub timelock: u64,
+    /// Addresses proposed for a role that haven't accepted it yet
+    pub pending_gov_module: Option<Addr>,
+    pub pending_ibc_module: Option<Addr>,
+    /// Whether governance turned on the usage of the quotas in the responses
+    /// of transfers. Builds with the verbose_responses feature add it anyway
+    pub verbose_responses: bool,
+}
+
+/// The usage of a quota once the simulated transfer is applied
+#[cw_serde]
+pub struct QuotaUsage {
+    pub channel_id: String,
+    pub denom: String,
+    /// Only set for address quotas
+    pub address: Option<String>,
+    pub name: String,
+    pub used: Uint256,
+    pub max: Uint256,
+    pub remaining: Uint256,
+    pub reset: Timestamp,
+}
+
+#[cw_serde]
+pub enum SimulateTransferResponse {
+    Allowed {
+        exemption: Option<ExemptionMode>,
+        quotas: Vec<QuotaUsage>,
     },
-    RecvPacket {
-        packet: Packet,
-        #[cfg(test)]
-        channel_value_mock: Option<Uint256>,
+    RateLimitExceeded {
+        /// Only set if an address quota was exceeded
+        address: Option<String>,
+        channel: String,
+        denom: String,
+        amount: Uint256,
+        quota_name: String,
+        used: Uint256,
+        max: Uint256,
+        reset: Timestamp,
     },
-    UndoSend {
-        packet: Packet,
+    Paused {
+        channel: String,
+        denom: String,
     },
 }
 
+/// The capacity left on a quota at the current block time. Usage is netted
+/// (unless the quota is gross) like when checking transfers
 #[cw_serde]
-pub enum MigrateMsg {}
+pub struct RemainingCapacity {
+    pub channel_id: String,
+    pub denom: String,
+    pub name: String,
+    pub used_in: Uint256,
+    pub used_out: Uint256,
+    pub remaining_in: Uint256,
+    pub remaining_out: Uint256,
+    pub period_end: Timestamp,
+    pub seconds_to_reset: u64,
+}
+
+#[cw_serde]
+pub enum SudoMsg {
+    SendPacket { packet: Packet },
+    RecvPacket { packet: Packet },
+    UndoSend { packet: Packet },
+}
+
+/// Migrations check the cw2 version stored by the previously deployed code and
+/// run, in order, every state transformation introduced since that version.
+#[cw_serde]
+pub enum MigrateMsg {
+    /// Upgrades the contract state. The gov and ibc module addresses can
+    /// optionally be replaced as part of the migration.
+    Upgrade {
+        gov_module: Option<Addr>,
+        ibc_module: Option<Addr>,
+    },
+}
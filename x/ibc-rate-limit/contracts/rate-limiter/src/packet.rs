@@ -1,5 +1,5 @@
 use crate::state::FlowType;
-use cosmwasm_std::{Addr, Deps, StdError, Uint256};
+use cosmwasm_std::{Addr, Uint256};
 use osmosis_std_derive::CosmwasmExt;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -75,8 +75,6 @@ pub struct QuerySupplyOfResponse {
 }
 // End of SupplyOf query message definition
 
-use std::str::FromStr; // Needed to parse the coin's String as Uint256
-
 fn hash_denom(denom: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(denom.as_bytes());
@@ -112,12 +110,15 @@ impl Packet {
         }
     }
 
-    pub fn channel_value(&self, deps: Deps, direction: &FlowType) -> Result<Uint256, StdError> {
-        let res = QuerySupplyOfRequest {
-            denom: self.local_denom(direction),
-        }
-        .query(&deps.querier)?;
-        Uint256::from_str(&res.amount.unwrap_or_default().amount)
+    pub fn with_addresses(mut self, sender: &str, receiver: &str) -> Packet {
+        self.data.sender = Addr::unchecked(sender);
+        self.data.receiver = Addr::unchecked(receiver);
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u64) -> Packet {
+        self.sequence = sequence;
+        self
     }
 
     pub fn get_funds(&self) -> Uint256 {
@@ -184,6 +185,15 @@ impl Packet {
     pub fn path_data(&self, direction: &FlowType) -> (String, String) {
         (self.local_channel(direction), self.local_denom(direction))
     }
+
+    /// The address on this chain that is moving the funds: the sender on sends
+    /// and the receiver on receives.
+    pub fn local_address(&self, direction: &FlowType) -> String {
+        match direction {
+            FlowType::In => self.data.receiver.to_string(),
+            FlowType::Out => self.data.sender.to_string(),
+        }
+    }
 }
 
 // Helpers
@@ -192,10 +202,9 @@ impl Packet {
 #[cfg(test)]
 #[macro_export]
 macro_rules! test_msg_send {
-    (channel_id: $channel_id:expr, denom: $denom:expr, channel_value: $channel_value:expr, funds: $funds:expr) => {
+    (channel_id: $channel_id:expr, denom: $denom:expr, funds: $funds:expr) => {
         $crate::msg::SudoMsg::SendPacket {
             packet: $crate::packet::Packet::mock($channel_id, $channel_id, $denom, $funds),
-            channel_value_mock: Some($channel_value),
         }
     };
 }
@@ -203,7 +212,7 @@ macro_rules! test_msg_send {
 #[cfg(test)]
 #[macro_export]
 macro_rules! test_msg_recv {
-    (channel_id: $channel_id:expr, denom: $denom:expr, channel_value: $channel_value:expr, funds: $funds:expr) => {
+    (channel_id: $channel_id:expr, denom: $denom:expr, funds: $funds:expr) => {
         $crate::msg::SudoMsg::RecvPacket {
             packet: $crate::packet::Packet::mock(
                 $channel_id,
@@ -211,7 +220,6 @@ macro_rules! test_msg_recv {
                 format!("transfer/{}/{}", $channel_id, $denom),
                 $funds,
             ),
-            channel_value_mock: Some($channel_value),
         }
     };
 }
@@ -304,7 +312,7 @@ pub mod tests {
         );
         assert_eq!(
             packet.local_denom(&FlowType::In),
-            WRAPPED_ATOM_ON_OSMOSIS_HASH.clone()
+            WRAPPED_ATOM_ON_OSMOSIS_HASH
         );
 
         // uatom on osmosis sent back to the hub
@@ -1,6 +1,16 @@
-use cosmwasm_std::{to_binary, Binary, Deps, StdResult};
+use cosmwasm_std::{to_binary, Binary, Deps, Order, StdError, StdResult, Timestamp, Uint256};
+use cw_storage_plus::Bound;
 
-use crate::state::{Path, RATE_LIMIT_TRACKERS};
+use crate::channel_value::{channel_value, ChannelValueProvider};
+use crate::msg::{Config, PathRateLimits, QuotaUsage, RemainingCapacity, SimulateTransferResponse};
+use crate::state::{
+    self, find_channel_value_source, find_exemption, FlowType, Path, RateLimit, ADDRESS_QUOTAS,
+    ADDRESS_RATE_LIMIT_TRACKERS, CHANNEL_RATE_LIMIT_TRACKERS, DENOM_WEIGHTS, GOVMODULE, GUARDIAN,
+    IBCMODULE, PATHS_BY_DENOM, PENDING_CHANGES, PENDING_GOVMODULE, PENDING_IBCMODULE,
+    RATE_LIMIT_TRACKERS, TIMELOCK, VERBOSE_RESPONSES,
+};
+use crate::sudo::check_transfer;
+use crate::ContractError;
 
 pub fn get_quotas(
     deps: Deps,
@@ -10,3 +20,348 @@ pub fn get_quotas(
     let path = Path::new(channel_id, denom);
     to_binary(&RATE_LIMIT_TRACKERS.load(deps.storage, path.into())?)
 }
+
+// Channels without quotas return an empty list
+pub fn get_channel_quotas(deps: Deps, channel_id: String) -> StdResult<Binary> {
+    to_binary(
+        &CHANNEL_RATE_LIMIT_TRACKERS
+            .may_load(deps.storage, channel_id)?
+            .unwrap_or_default(),
+    )
+}
+
+pub fn get_denom_weight(deps: Deps, denom: String) -> StdResult<Binary> {
+    to_binary(&DENOM_WEIGHTS.may_load(deps.storage, denom)?)
+}
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub fn list_paths(
+    deps: Deps,
+    start_after: Option<(String, String)>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let paths = RATE_LIMIT_TRACKERS
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let ((channel_id, denom), rate_limits) = item?;
+            Ok(PathRateLimits {
+                channel_id,
+                denom,
+                rate_limits,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    to_binary(&paths)
+}
+
+pub fn list_paths_for_channel(
+    deps: Deps,
+    channel_id: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let paths = RATE_LIMIT_TRACKERS
+        .prefix(channel_id.clone())
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (denom, rate_limits) = item?;
+            Ok(PathRateLimits {
+                channel_id: channel_id.clone(),
+                denom,
+                rate_limits,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    to_binary(&paths)
+}
+
+// Uses the PATHS_BY_DENOM index to find the channels of the denom
+pub fn list_paths_for_denom(
+    deps: Deps,
+    denom: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let paths = PATHS_BY_DENOM
+        .prefix(denom.clone())
+        .keys(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|channel_id| {
+            let channel_id = channel_id?;
+            let rate_limits =
+                RATE_LIMIT_TRACKERS.load(deps.storage, (channel_id.clone(), denom.clone()))?;
+            Ok(PathRateLimits {
+                channel_id,
+                denom: denom.clone(),
+                rate_limits,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    to_binary(&paths)
+}
+
+// Periods that have ended are reported the way the next transfer will see
+// them: with no usage and a full period ahead. The capacity relies on the
+// cached channel value, which is only refreshed by transfers.
+pub fn get_remaining_capacity(
+    deps: Deps,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+    now: Timestamp,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+    let any_path = Path::new("any", path.denom.clone());
+
+    let mut capacities = vec![];
+    for quota_path in [&path, &any_path] {
+        let trackers = RATE_LIMIT_TRACKERS
+            .may_load(deps.storage, quota_path.into())?
+            .unwrap_or_default();
+        for limit in trackers {
+            let limit = limit.at(now);
+            let (used_in, used_out) = limit.flow.usage(limit.quota.gross);
+            let (max_in, max_out) = limit.quota.capacity();
+            capacities.push(RemainingCapacity {
+                channel_id: quota_path.channel.clone(),
+                denom: quota_path.denom.clone(),
+                name: limit.quota.name,
+                used_in,
+                used_out,
+                remaining_in: limit
+                    .flow
+                    .remaining_on(&FlowType::In, max_in, limit.quota.gross),
+                remaining_out: limit
+                    .flow
+                    .remaining_on(&FlowType::Out, max_out, limit.quota.gross),
+                period_end: limit.flow.period_end,
+                seconds_to_reset: limit
+                    .flow
+                    .period_end
+                    .seconds()
+                    .saturating_sub(now.seconds()),
+            });
+        }
+    }
+    to_binary(&capacities)
+}
+
+// Returns the address quotas of the path along with the flow of the address.
+// Addresses that haven't transferred yet get a fresh flow.
+pub fn get_address_quotas(
+    deps: Deps,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+    address: impl Into<String>,
+    now: Timestamp,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+    let quotas = ADDRESS_QUOTAS.load(deps.storage, (&path).into())?;
+    let existing = ADDRESS_RATE_LIMIT_TRACKERS
+        .may_load(deps.storage, (path.channel, path.denom, address.into()))?
+        .unwrap_or_default();
+    to_binary(&RateLimit::for_quotas(&quotas, existing, now))
+}
+
+pub fn get_exemption(
+    deps: Deps,
+    address: String,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+    to_binary(&find_exemption(deps.storage, &address, &path)?)
+}
+
+pub fn get_config(deps: Deps) -> StdResult<Binary> {
+    to_binary(&Config {
+        gov_module: GOVMODULE.load(deps.storage)?,
+        ibc_module: IBCMODULE.load(deps.storage)?,
+        guardian: GUARDIAN.may_load(deps.storage)?,
+        timelock: TIMELOCK.may_load(deps.storage)?.unwrap_or_default(),
+        pending_gov_module: PENDING_GOVMODULE.may_load(deps.storage)?,
+        pending_ibc_module: PENDING_IBCMODULE.may_load(deps.storage)?,
+        verbose_responses: VERBOSE_RESPONSES
+            .may_load(deps.storage)?
+            .unwrap_or_default(),
+    })
+}
+
+pub fn get_pending_changes(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let changes = PENDING_CHANGES
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, change)| change))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_binary(&changes)
+}
+
+pub fn get_channel_value_source(
+    deps: Deps,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+    to_binary(&find_channel_value_source(deps.storage, &path)?)
+}
+
+pub fn is_paused(
+    deps: Deps,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+    to_binary(&state::is_paused(deps.storage, &path))
+}
+
+// Runs the same checks as a transfer, but doesn't store the results. The
+// channel value is obtained the same way as when processing packets.
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_transfer(
+    deps: Deps,
+    channel_id: impl Into<String>,
+    denom: impl Into<String>,
+    amount: Uint256,
+    direction: FlowType,
+    address: Option<String>,
+    now: Timestamp,
+    provider: &dyn ChannelValueProvider,
+) -> StdResult<Binary> {
+    let path = Path::new(channel_id, denom);
+
+    // The simulated transfer hasn't happened yet, so the current value doesn't
+    // need to be adjusted like when processing packets
+    let channel_value = channel_value(deps.storage, provider, &path)?;
+
+    let check = check_transfer(
+        deps.storage,
+        &path,
+        address.as_deref(),
+        channel_value,
+        amount,
+        &direction,
+        now,
+        provider,
+    );
+    let response = match check {
+        Ok(check) => {
+            let usage =
+                |channel_id: &str, denom: &str, address: Option<&str>, limit: &RateLimit| {
+                    let used = limit.flow.usage_on(&direction, limit.quota.gross);
+                    let max = limit.quota.capacity_on(&direction);
+                    QuotaUsage {
+                        channel_id: channel_id.to_string(),
+                        denom: denom.to_string(),
+                        address: address.map(String::from),
+                        name: limit.quota.name.clone(),
+                        used,
+                        max,
+                        remaining: limit.flow.remaining_on(&direction, max, limit.quota.gross),
+                        reset: limit.flow.period_end,
+                    }
+                };
+            let quotas = check
+                .trackers
+                .iter()
+                .map(|limit| usage(&path.channel, &path.denom, None, limit))
+                .chain(
+                    check
+                        .any_trackers
+                        .iter()
+                        .map(|limit| usage("any", &path.denom, None, limit)),
+                )
+                .chain(check.address_trackers.iter().flat_map(
+                    |((channel_id, denom, address), limits)| {
+                        limits
+                            .iter()
+                            .map(|limit| usage(channel_id, denom, Some(address), limit))
+                    },
+                ))
+                .chain(
+                    check
+                        .channel_trackers
+                        .iter()
+                        .map(|limit| usage(&path.channel, "any", None, limit)),
+                )
+                .collect();
+            SimulateTransferResponse::Allowed {
+                exemption: check.exemption,
+                quotas,
+            }
+        }
+        Err(ContractError::RateLimitExceded {
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        }) => SimulateTransferResponse::RateLimitExceeded {
+            address: None,
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        },
+        Err(ContractError::AddressRateLimitExceded {
+            address,
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        }) => SimulateTransferResponse::RateLimitExceeded {
+            address: Some(address),
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        },
+        Err(ContractError::Paused { channel, denom }) => {
+            SimulateTransferResponse::Paused { channel, denom }
+        }
+        Err(ContractError::Std(err)) => return Err(err),
+        Err(err) => return Err(StdError::generic_err(err.to_string())),
+    };
+    to_binary(&response)
+}
@@ -1,11 +1,14 @@
-use cosmwasm_std::{Addr, Timestamp, Uint256};
+use cosmwasm_std::{Addr, Decimal, Empty, Order, StdResult, Storage, Timestamp, Uint128, Uint256};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::cmp;
 
 use cw_storage_plus::{Item, Map};
 
-use crate::{msg::QuotaMsg, ContractError};
+use crate::{
+    msg::{ExecuteMsg, QuotaMsg},
+    ContractError,
+};
 
 /// This represents the key for our rate limiting tracker. A tuple of a denom and
 /// a channel. When interactic with storage, it's preffered to use this struct
@@ -38,7 +41,8 @@ impl From<&Path> for (String, String) {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum FlowType {
     In,
     Out,
@@ -51,7 +55,9 @@ pub enum FlowType {
 /// osmosis).
 ///
 /// The period_end represents the last point in time for which this Flow is
-/// tracking the value transfer.
+/// tracking the value transfer, and period_start the moment the period began.
+/// Updating a quota can move the end of its current period, so periods are
+/// identified by their start.
 ///
 /// Periods are discrete repeating windows. A period only starts when a contract
 /// call to update the Flow (SendPacket/RecvPackt) is made, and not right after
@@ -59,12 +65,18 @@ pub enum FlowType {
 /// the next period will begin at the time of the next call and be valid for the
 /// specified duration for the quota.
 ///
+/// Quotas can instead be aligned to fixed boundaries. Their periods always end
+/// at the next boundary, no matter when the period started, so that users can
+/// predict when the quota resets.
+///
 /// This is a design decision to avoid the period calculations and thus reduce gas consumption
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema, Copy)]
 pub struct Flow {
     pub inflow: Uint256,
     pub outflow: Uint256,
     pub period_end: Timestamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_start: Option<Timestamp>,
 }
 
 impl Flow {
@@ -78,6 +90,7 @@ impl Flow {
             inflow: inflow.into(),
             outflow: outflow.into(),
             period_end: now.plus_seconds(duration),
+            period_start: Some(now),
         }
     }
 
@@ -93,21 +106,51 @@ impl Flow {
         )
     }
 
+    /// The value counted towards the quota in each direction. Flows are netted
+    /// by default. Gross flows count inflows and outflows independently, so
+    /// that moving funds back and forth doesn't make room for larger transfers.
+    pub fn usage(&self, gross: bool) -> (Uint256, Uint256) {
+        match gross {
+            true => (self.inflow, self.outflow),
+            false => self.balance(),
+        }
+    }
+
     /// checks if the flow, in the current state, has exceeded a max allowance
-    pub fn exceeds(&self, direction: &FlowType, max_inflow: Uint256, max_outflow: Uint256) -> bool {
-        let (balance_in, balance_out) = self.balance();
+    pub fn exceeds(
+        &self,
+        direction: &FlowType,
+        max_inflow: Uint256,
+        max_outflow: Uint256,
+        gross: bool,
+    ) -> bool {
+        let (used_in, used_out) = self.usage(gross);
         match direction {
-            FlowType::In => balance_in > max_inflow,
-            FlowType::Out => balance_out > max_outflow,
+            FlowType::In => used_in > max_inflow,
+            FlowType::Out => used_out > max_outflow,
         }
     }
 
-    /// returns the balance in a direction. This is used for displaying cleaner errors
-    pub fn balance_on(&self, direction: &FlowType) -> Uint256 {
-        let (balance_in, balance_out) = self.balance();
+    /// returns how much more value can be transferred in a direction without
+    /// exceeding the max allowance. Unless the flow is gross, value
+    /// transferred in the opposite direction frees up capacity
+    pub fn remaining_on(&self, direction: &FlowType, max: Uint256, gross: bool) -> Uint256 {
+        let (same, opposite) = match direction {
+            FlowType::In => (self.inflow, self.outflow),
+            FlowType::Out => (self.outflow, self.inflow),
+        };
+        match gross {
+            true => max.saturating_sub(same),
+            false => max.saturating_add(opposite).saturating_sub(same),
+        }
+    }
+
+    /// returns the usage in a direction. This is used for displaying cleaner errors
+    pub fn usage_on(&self, direction: &FlowType, gross: bool) -> Uint256 {
+        let (used_in, used_out) = self.usage(gross);
         match direction {
-            FlowType::In => balance_in,
-            FlowType::Out => balance_out,
+            FlowType::In => used_in,
+            FlowType::Out => used_out,
         }
     }
 
@@ -119,11 +162,13 @@ impl Flow {
     // Mutating methods
 
     /// Expire resets the Flow to start tracking the value transfer from the
-    /// moment this method is called.
-    pub fn expire(&mut self, now: Timestamp, duration: u64) {
+    /// moment this method is called. The new period ends after the duration of
+    /// the quota or, for aligned quotas, at the next boundary.
+    pub fn expire(&mut self, now: Timestamp, quota: &Quota) {
         self.inflow = Uint256::from(0_u32);
         self.outflow = Uint256::from(0_u32);
-        self.period_end = now.plus_seconds(duration);
+        self.period_end = quota.period_end(now);
+        self.period_start = Some(now);
     }
 
     /// Updates the current flow incrementing it by a transfer of value.
@@ -153,7 +198,7 @@ impl Flow {
     ) -> bool {
         let mut expired = false;
         if self.is_expired(now) {
-            self.expire(now, quota.duration);
+            self.expire(now, quota);
             expired = true;
         }
         self.add_flow(direction.clone(), funds);
@@ -161,37 +206,180 @@ impl Flow {
     }
 }
 
+/// A Bucket tracks the value transferred during one sub-period of a sliding
+/// window quota. It starts at the time of the first transfer after the
+/// previous bucket ended.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema, Copy)]
+pub struct Bucket {
+    pub inflow: Uint256,
+    pub outflow: Uint256,
+    pub start: Timestamp,
+}
+
+impl Bucket {
+    pub fn new(start: Timestamp) -> Self {
+        Self {
+            inflow: Uint256::from(0_u32),
+            outflow: Uint256::from(0_u32),
+            start,
+        }
+    }
+}
+
+/// QuotaKind determines how the value transferred through a path is tracked
+/// over time.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    /// Discrete periods of `duration` seconds. All the flow is reset when the
+    /// period ends.
+    Fixed,
+    /// A rolling window of `duration` seconds, split into `buckets`
+    /// sub-periods. The value transferred during a sub-period stops counting
+    /// towards the quota once that sub-period started more than `duration`
+    /// seconds ago. More buckets make the window more precise at the cost of
+    /// storing more data.
+    SlidingWindow { buckets: u32 },
+    /// The used capacity refills continuously, at a rate that takes `duration`
+    /// seconds to refill the whole quota. This avoids the whole capacity
+    /// becoming available at once when a period ends.
+    TokenBucket,
+}
+
+impl Default for QuotaKind {
+    fn default() -> Self {
+        QuotaKind::Fixed
+    }
+}
+
+/// QuotaPrice measures the transfers of a quota in a reference denom, using
+/// the arithmetic TWAP of an Osmosis pool to convert them. This allows quotas
+/// of different denoms to have comparable capacities (i.e.: $1M a day).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct QuotaPrice {
+    /// The denom the amounts of the quota are expressed in
+    pub reference_denom: String,
+    /// The pool that prices the denom of the path in the reference denom
+    pub pool_id: u64,
+    /// The number of seconds the TWAP is averaged over
+    pub window: u64,
+}
+
+/// The longest window prices can be averaged over. Osmosis only keeps TWAP
+/// records for 48 hours.
+pub const MAX_PRICE_WINDOW: u64 = 48 * 60 * 60;
+
 /// A Quota is the percentage of the denom's total value that can be transferred
 /// through the channel in a given period of time (duration)
 ///
-/// Percentages can be different for send and recv
+/// Percentages can be different for send and recv. They are decimals, so
+/// quotas smaller than 1% (i.e.: 0.25%) can be expressed.
+///
+/// A quota can also (or instead) cap the absolute amount of the denom that can
+/// be transferred in each direction. This is useful for denoms with a thin
+/// supply, for which a percentage of the supply is not meaningful. If both
+/// limits are set, the tighter one applies.
 ///
 /// The name of the quota is expected to be a human-readable representation of
 /// the duration (i.e.: "weekly", "daily", "every-six-months", ...)
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct Quota {
     pub name: String,
-    pub max_percentage_send: u32,
-    pub max_percentage_recv: u32,
+    pub max_percentage_send: Option<Decimal>,
+    pub max_percentage_recv: Option<Decimal>,
+    #[serde(default)]
+    pub max_amount_send: Option<Uint256>,
+    #[serde(default)]
+    pub max_amount_recv: Option<Uint256>,
     pub duration: u64,
     pub channel_value: Option<Uint256>,
+    #[serde(default)]
+    pub kind: QuotaKind,
+    /// Offset from the Unix epoch of the boundaries that periods are aligned
+    /// to. Periods are not aligned if it isn't set
+    #[serde(default)]
+    pub alignment: Option<u64>,
+    /// Checks inflows and outflows independently instead of netting them
+    #[serde(default)]
+    pub gross: bool,
+    /// Measures the transfers in a reference denom instead of in the denom of
+    /// the path. The amounts of the quota are then in the reference denom
+    #[serde(default)]
+    pub price: Option<QuotaPrice>,
+    /// The price of the last transfer. It is used if the pool has no TWAP
+    #[serde(default)]
+    pub last_price: Option<Decimal>,
 }
 
 impl Quota {
+    /// Returns the end of a period that starts at `now`. Aligned periods end at
+    /// the first boundary after `now`, so the first period can be shorter than
+    /// the duration.
+    pub fn period_end(&self, now: Timestamp) -> Timestamp {
+        // An unused token bucket is already full
+        if self.kind == QuotaKind::TokenBucket {
+            return now;
+        }
+        let alignment = match self.alignment {
+            Some(alignment) => alignment,
+            None => return now.plus_seconds(self.duration),
+        };
+        let offset = alignment % self.duration;
+        let elapsed = (now.seconds() + self.duration - offset) % self.duration;
+        now.minus_nanos(now.subsec_nanos())
+            .plus_seconds(self.duration - elapsed)
+    }
+
     /// Calculates the max capacity (absolute value in the same unit as
     /// total_value) in each direction based on the total value of the denom in
-    /// the channel. The result tuple represents the max capacity when the
-    /// transfer is in directions: (FlowType::In, FlowType::Out)
+    /// the channel and the absolute amounts allowed by the quota. The result
+    /// tuple represents the max capacity when the transfer is in directions:
+    /// (FlowType::In, FlowType::Out)
+    ///
+    /// Capacities are rounded down, so a quota never allows transferring more
+    /// than its percentage of the channel value.
     pub fn capacity(&self) -> (Uint256, Uint256) {
-        match self.channel_value {
-            Some(total_value) => (
-                total_value * Uint256::from(self.max_percentage_recv) / Uint256::from(100_u32),
-                total_value * Uint256::from(self.max_percentage_send) / Uint256::from(100_u32),
+        (
+            self.limit(self.max_percentage_recv, self.max_amount_recv),
+            self.limit(self.max_percentage_send, self.max_amount_send),
+        )
+    }
+
+    fn limit(&self, percentage: Option<Decimal>, amount: Option<Uint256>) -> Uint256 {
+        let relative = percentage.map(|percentage| match self.channel_value {
+            Some(total_value) => total_value.multiply_ratio(
+                percentage.atomics(),
+                Decimal::one().atomics() * Uint128::from(100_u32),
             ),
-            None => (0_u32.into(), 0_u32.into()), // This should never happen, but ig the channel value is not set, we disallow any transfer
+            None => 0_u32.into(), // This should never happen, but ig the channel value is not set, we disallow any transfer
+        });
+        match (relative, amount) {
+            (Some(relative), Some(amount)) => cmp::min(relative, amount),
+            (Some(limit), None) | (None, Some(limit)) => limit,
+            (None, None) => 0_u32.into(), // A quota without limits does not allow any transfer
         }
     }
 
+    /// Checks if this quota could allow more value to be transferred than
+    /// `other` in any direction. Quotas of a different kind can't be compared,
+    /// so they are always considered looser. So are changes to the alignment,
+    /// which can end the current period (and reset its flow) early.
+    pub fn is_looser_than(&self, other: &Quota) -> bool {
+        self.kind != other.kind
+            || self.alignment != other.alignment
+            || self.price != other.price
+            || (other.gross && !self.gross)
+            || self.duration < other.duration
+            || limit_is_looser(
+                (self.max_percentage_send, self.max_amount_send),
+                (other.max_percentage_send, other.max_amount_send),
+            )
+            || limit_is_looser(
+                (self.max_percentage_recv, self.max_amount_recv),
+                (other.max_percentage_recv, other.max_amount_recv),
+            )
+    }
+
     /// returns the capacity in a direction. This is used for displaying cleaner errors
     pub fn capacity_on(&self, direction: &FlowType) -> Uint256 {
         let (max_in, max_out) = self.capacity();
@@ -202,57 +390,515 @@ impl Quota {
     }
 }
 
-impl From<&QuotaMsg> for Quota {
-    fn from(msg: &QuotaMsg) -> Self {
-        let send_recv = (
-            cmp::min(msg.send_recv.0, 100),
-            cmp::min(msg.send_recv.1, 100),
-        );
-        Quota {
+// A direction without any limit doesn't allow transfers, so adding a limit to
+// it loosens the quota. Otherwise, the limit is the minimum of the percentage
+// and the amount, and removing or raising either of them loosens it.
+fn limit_is_looser(
+    (percentage, amount): (Option<Decimal>, Option<Uint256>),
+    (other_percentage, other_amount): (Option<Decimal>, Option<Uint256>),
+) -> bool {
+    if other_percentage.is_none() && other_amount.is_none() {
+        return percentage.is_some() || amount.is_some();
+    }
+    let bound_is_looser = |new: Option<Uint256>, old: Option<Uint256>| match (new, old) {
+        (Some(new), Some(old)) => new > old,
+        (None, Some(_)) => true,
+        (_, None) => false,
+    };
+    let as_atomics = |percentage: Option<Decimal>| percentage.map(|p| Uint256::from(p.atomics()));
+    bound_is_looser(as_atomics(percentage), as_atomics(other_percentage))
+        || bound_is_looser(amount, other_amount)
+}
+
+/// Checks if replacing the `current` quotas with `new` ones could allow more
+/// value to be transferred. Quotas are matched by name, so removing or
+/// renaming a quota loosens the limits.
+pub fn loosens_quotas(current: &[Quota], new: &[Quota]) -> bool {
+    current.iter().any(
+        |quota| match new.iter().find(|new_quota| new_quota.name == quota.name) {
+            Some(new_quota) => new_quota.is_looser_than(quota),
+            None => true,
+        },
+    )
+}
+
+/// The longest duration a quota can have (100 years), so that period ends don't overflow
+pub const MAX_QUOTA_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// The most buckets a sliding window can be split into (i.e.: hourly buckets
+/// for a weekly quota). Every transfer goes through the buckets of the window,
+/// so they are bounded to keep the gas of a transfer low.
+pub const MAX_SLIDING_WINDOW_BUCKETS: u32 = 168;
+
+/// Quotas are validated when they are created from a message. Invalid
+/// configurations are rejected instead of being stored.
+impl TryFrom<&QuotaMsg> for Quota {
+    type Error = ContractError;
+
+    fn try_from(msg: &QuotaMsg) -> Result<Self, Self::Error> {
+        if msg.name.is_empty() {
+            return Err(ContractError::EmptyQuotaName {});
+        }
+        if msg.duration == 0 {
+            return Err(ContractError::ZeroQuotaDuration {
+                quota_id: msg.name.clone(),
+            });
+        }
+        if msg.duration > MAX_QUOTA_DURATION {
+            return Err(ContractError::QuotaDurationTooLong {
+                quota_id: msg.name.clone(),
+                max: MAX_QUOTA_DURATION,
+            });
+        }
+        if msg.send_recv.is_none() && msg.send_recv_amounts.is_none() {
+            return Err(ContractError::MissingQuotaLimits {
+                quota_id: msg.name.clone(),
+            });
+        }
+        if let QuotaKind::SlidingWindow { buckets } = msg.kind {
+            if buckets == 0 {
+                return Err(ContractError::ZeroQuotaBuckets {
+                    quota_id: msg.name.clone(),
+                });
+            }
+            // Buckets last at least a second
+            let max = cmp::min(u64::from(MAX_SLIDING_WINDOW_BUCKETS), msg.duration);
+            if u64::from(buckets) > max {
+                return Err(ContractError::TooManyQuotaBuckets {
+                    quota_id: msg.name.clone(),
+                    max,
+                });
+            }
+        }
+        let send_recv = msg
+            .send_recv
+            .map(|(send, recv)| (Decimal::from(send), Decimal::from(recv)));
+        if msg.alignment.is_some() && msg.kind != QuotaKind::Fixed {
+            return Err(ContractError::InvalidQuotaAlignment {
+                quota_id: msg.name.clone(),
+            });
+        }
+        // The channel value is in the denom of the path, so percentages can't
+        // be compared with the priced value of the transfers
+        if msg.price.is_some() && (msg.send_recv.is_some() || msg.send_recv_amounts.is_none()) {
+            return Err(ContractError::InvalidPricedQuota {
+                quota_id: msg.name.clone(),
+            });
+        }
+        if let Some(price) = &msg.price {
+            if price.window == 0 || price.window > MAX_PRICE_WINDOW {
+                return Err(ContractError::InvalidPriceWindow {
+                    quota_id: msg.name.clone(),
+                    max: MAX_PRICE_WINDOW,
+                });
+            }
+        }
+        let max = Decimal::from_ratio(100_u32, 1_u32);
+        if let Some((send, recv)) = send_recv {
+            if let Some(percentage) = [send, recv].into_iter().find(|p| *p > max) {
+                return Err(ContractError::InvalidQuotaPercentage {
+                    quota_id: msg.name.clone(),
+                    percentage,
+                });
+            }
+        }
+        Ok(Quota {
             name: msg.name.clone(),
-            max_percentage_send: send_recv.0,
-            max_percentage_recv: send_recv.1,
+            max_percentage_send: send_recv.map(|send_recv| send_recv.0),
+            max_percentage_recv: send_recv.map(|send_recv| send_recv.1),
+            max_amount_send: msg.send_recv_amounts.map(|amounts| amounts.0),
+            max_amount_recv: msg.send_recv_amounts.map(|amounts| amounts.1),
             duration: msg.duration,
             channel_value: None,
-        }
+            kind: msg.kind,
+            alignment: msg.alignment,
+            gross: msg.gross,
+            price: msg.price.clone(),
+            last_price: None,
+        })
+    }
+}
+
+/// Builds the quotas of a path. Each quota must be valid and have a unique
+/// name within the path.
+pub fn build_quotas(path: &Path, msgs: &[QuotaMsg]) -> Result<Vec<Quota>, ContractError> {
+    msgs.iter()
+        .enumerate()
+        .map(|(i, msg)| {
+            if msgs[..i].iter().any(|other| other.name == msg.name) {
+                return Err(ContractError::DuplicateQuotaName {
+                    quota_id: msg.name.clone(),
+                    channel_id: path.channel.clone(),
+                    denom: path.denom.clone(),
+                });
+            }
+            Quota::try_from(msg)
+        })
+        .collect()
+}
+
+/// Builds the quotas of a whole channel. Their flow is measured in weighted
+/// value instead of in amounts of a denom, so percentages of the channel value
+/// don't apply to them.
+pub fn build_channel_quotas(
+    channel_id: &str,
+    msgs: &[QuotaMsg],
+) -> Result<Vec<Quota>, ContractError> {
+    // Channel quotas use the weight of each denom instead of a price
+    if let Some(msg) = msgs.iter().find(|msg| {
+        msg.send_recv.is_some() || msg.send_recv_amounts.is_none() || msg.price.is_some()
+    }) {
+        return Err(ContractError::InvalidChannelQuota {
+            quota_id: msg.name.clone(),
+        });
     }
+    build_quotas(&Path::new(channel_id, "any"), msgs)
 }
 
 /// RateLimit is the main structure tracked for each channel/denom pair. Its quota
 /// represents rate limit configuration, and the flow its
 /// current state (i.e.: how much value has been transfered in the current period)
+///
+/// For sliding window quotas, the flow is the sum of all the buckets in the
+/// window and its period_end is the moment in which the oldest bucket stops
+/// counting towards the quota.
+///
+/// For token bucket quotas, the flow is the (netted) capacity in use and its
+/// period_end is the moment in which the whole capacity will have refilled.
+/// Each direction refills at its own rate, so last_refill keeps the point up to
+/// which each of them has been refilled as (inflow, outflow).
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct RateLimit {
     pub quota: Quota,
     pub flow: Flow,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<Bucket>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_refill: Option<(Timestamp, Timestamp)>,
 }
 
-// The channel value on send depends on the amount on escrow. The ibc transfer
-// module modifies the escrow amount by "funds" on sends before calling the
-// contract. This function takes that into account so that the channel value
-// that we track matches the channel value at the moment when the ibc
-// transaction started executing
-fn calculate_channel_value(
-    channel_value: Uint256,
-    denom: &str,
-    funds: Uint256,
-    direction: &FlowType,
-) -> Uint256 {
-    match direction {
-        FlowType::Out => {
-            if denom.contains("ibc") {
-                channel_value + funds // Non-Native tokens get removed from the supply on send. Add that amount back
-            } else {
-                // The commented-out code in the golang calculate channel value is what we want, but we're currently using the whole supply temporarily for efficiency. see rate_limit.go/CalculateChannelValue(..)
-                //channel_value - funds // Native tokens increase escrow amount on send. Remove that amount here
-                channel_value
+fn saturating_u64(value: Uint256) -> u64 {
+    Uint128::try_from(value)
+        .ok()
+        .and_then(|value| u64::try_from(value.u128()).ok())
+        .unwrap_or(u64::MAX)
+}
+
+impl RateLimit {
+    pub fn new(quota: Quota, now: Timestamp) -> Self {
+        let flow = Flow {
+            inflow: Uint256::zero(),
+            outflow: Uint256::zero(),
+            period_end: quota.period_end(now),
+            period_start: Some(now),
+        };
+        RateLimit {
+            quota,
+            flow,
+            buckets: vec![],
+            last_refill: None,
+        }
+    }
+
+    /// Applies a transfer to the flow. Returns true if a new period (or, for
+    /// sliding windows, a new bucket) started with this transfer.
+    fn apply_transfer(&mut self, direction: &FlowType, funds: Uint256, now: Timestamp) -> bool {
+        match self.quota.kind {
+            QuotaKind::Fixed => self.flow.apply_transfer(direction, funds, now, &self.quota),
+            QuotaKind::SlidingWindow { buckets } => {
+                let new_bucket = self.roll_window(now, buckets);
+                self.flow.add_flow(direction.clone(), funds);
+                if let Some(bucket) = self.buckets.last_mut() {
+                    match direction {
+                        FlowType::In => bucket.inflow = bucket.inflow.saturating_add(funds),
+                        FlowType::Out => bucket.outflow = bucket.outflow.saturating_add(funds),
+                    }
+                }
+                new_bucket
+            }
+            QuotaKind::TokenBucket => {
+                let full = self.refill(now);
+                self.flow.add_flow(direction.clone(), funds);
+                self.flow.period_end = self.full_at(now);
+                full
             }
         }
-        FlowType::In => channel_value,
     }
-}
 
-impl RateLimit {
+    /// Frees up the capacity that has refilled since the last refill. Returns
+    /// true if the bucket was full.
+    fn refill(&mut self, now: Timestamp) -> bool {
+        let (last_in, last_out) = self.last_refill.unwrap_or((now, now));
+        let (used_in, used_out) = self.flow.usage(self.quota.gross);
+        let (max_in, max_out) = self.quota.capacity();
+
+        let (used_in, last_in) = self.refill_direction(used_in, max_in, last_in, now);
+        let (used_out, last_out) = self.refill_direction(used_out, max_out, last_out, now);
+        self.flow.inflow = used_in;
+        self.flow.outflow = used_out;
+        self.last_refill = Some((last_in, last_out));
+        used_in.is_zero() && used_out.is_zero()
+    }
+
+    /// Refills the capacity in use in one direction since `last_refill`.
+    /// Returns the capacity still in use and the point up to which it has been
+    /// refilled. Refills are rounded down, so only the time it took to refill
+    /// the whole units is consumed and frequent transfers don't stop the
+    /// refill.
+    fn refill_direction(
+        &self,
+        used: Uint256,
+        max: Uint256,
+        last_refill: Timestamp,
+        now: Timestamp,
+    ) -> (Uint256, Timestamp) {
+        let duration = self.quota.duration.saturating_mul(1_000_000_000);
+        let elapsed = now.nanos().saturating_sub(last_refill.nanos());
+        let refilled = max.multiply_ratio(elapsed, duration);
+        // A full direction has nothing left to refill, and one without capacity
+        // never refills, so neither of them keeps time they could use later
+        if refilled >= used || max.is_zero() {
+            return (used.saturating_sub(refilled), now);
+        }
+        let refill_time = saturating_u64(refilled.multiply_ratio(duration, max));
+        (used - refilled, last_refill.plus_nanos(refill_time))
+    }
+
+    /// Returns the moment in which the capacity in use will have refilled
+    fn full_at(&self, now: Timestamp) -> Timestamp {
+        let duration = self.quota.duration.saturating_mul(1_000_000_000);
+        let (used_in, used_out) = self.flow.usage(self.quota.gross);
+        let (max_in, max_out) = self.quota.capacity();
+        let refill_time = |used: Uint256, max: Uint256| -> u64 {
+            if used.is_zero() {
+                return 0;
+            }
+            if max.is_zero() {
+                return duration;
+            }
+            saturating_u64(used.multiply_ratio(duration, max))
+        };
+        let nanos = cmp::max(refill_time(used_in, max_in), refill_time(used_out, max_out));
+        Timestamp::from_nanos(now.nanos().saturating_add(nanos))
+    }
+
+    /// Removes the buckets that are no longer part of the window from the flow
+    /// and makes sure there is a bucket accepting transfers at `now`. Returns
+    /// true if a new bucket was started.
+    fn roll_window(&mut self, now: Timestamp, buckets: u32) -> bool {
+        let duration = self.quota.duration;
+        let bucket_duration = cmp::max(duration / cmp::max(buckets, 1) as u64, 1);
+
+        let expired = self
+            .buckets
+            .iter()
+            .take_while(|bucket| bucket.start.plus_seconds(duration) < now)
+            .count();
+        for bucket in self.buckets.drain(..expired) {
+            self.flow.undo_flow(FlowType::In, bucket.inflow);
+            self.flow.undo_flow(FlowType::Out, bucket.outflow);
+        }
+
+        let new_bucket = match self.buckets.last() {
+            Some(bucket) => bucket.start.plus_seconds(bucket_duration) <= now,
+            None => true,
+        };
+        if new_bucket {
+            self.buckets.push(Bucket::new(now));
+        }
+        // The window can only free capacity once its oldest bucket expires
+        self.flow.period_end = self.buckets[0].start.plus_seconds(duration);
+        new_bucket
+    }
+
+    /// Replaces the quota, keeping the flow when it is still meaningful. The
+    /// current period keeps its start but lasts the new duration. Changing the
+    /// kind of quota changes how the flow is tracked, so it starts a new flow.
+    pub fn update_quota(&mut self, quota: Quota, now: Timestamp) {
+        if quota.kind != self.quota.kind {
+            *self = RateLimit::new(quota, now);
+            return;
+        }
+        if quota.kind == QuotaKind::TokenBucket {
+            // The capacity used so far refills at the rate of the new quota
+            self.refill(now);
+            self.quota = Quota {
+                channel_value: self.quota.channel_value,
+                last_price: self.quota.last_price,
+                ..quota
+            };
+            self.flow.period_end = self.full_at(now);
+            return;
+        }
+        let start = match (self.buckets.first(), self.flow.period_start) {
+            (Some(bucket), _) => bucket.start,
+            (None, Some(start)) => start,
+            (None, None) => self.flow.period_end.minus_seconds(self.quota.duration),
+        };
+        self.flow.period_end = match quota.alignment {
+            Some(_) => quota.period_end(start),
+            None => start.plus_seconds(quota.duration),
+        };
+        self.quota = Quota {
+            channel_value: self.quota.channel_value,
+            last_price: self.quota.last_price,
+            ..quota
+        };
+    }
+
+    /// Returns the rate limit as the next transfer would find it at `now`. That
+    /// is, without the value of the periods (or buckets) that have expired.
+    pub fn at(&self, now: Timestamp) -> Self {
+        let mut limit = self.clone();
+        limit.apply_transfer(&FlowType::In, Uint256::zero(), now);
+        limit
+    }
+
+    /// Checks if nothing transferred so far still counts towards the quota at
+    /// `now`, either because it expired or because it was undone
+    pub fn is_unused(&self, now: Timestamp) -> bool {
+        let limit = self.at(now);
+        limit.flow.inflow.is_zero() && limit.flow.outflow.is_zero()
+    }
+
+    /// Reverts a transfer that was previously applied. For sliding windows the
+    /// value is removed from the most recent buckets first.
+    pub fn undo_transfer(&mut self, direction: FlowType, funds: Uint256) {
+        self.flow.undo_flow(direction.clone(), funds);
+        let mut remaining = funds;
+        for bucket in self.buckets.iter_mut().rev() {
+            let amount = match direction {
+                FlowType::In => &mut bucket.inflow,
+                FlowType::Out => &mut bucket.outflow,
+            };
+            let undone = cmp::min(*amount, remaining);
+            *amount -= undone;
+            remaining -= undone;
+            if remaining.is_zero() {
+                break;
+            }
+        }
+    }
+
+    /// Identifies the period in which a transfer applied at `now` was counted:
+    /// the start of the period for fixed quotas, the start of the bucket for
+    /// sliding windows and the time of the transfer for token buckets.
+    pub fn period_of_transfer(&self, now: Timestamp) -> Timestamp {
+        match self.quota.kind {
+            QuotaKind::Fixed => self.flow.period_start.unwrap_or(self.flow.period_end),
+            QuotaKind::SlidingWindow { .. } => self
+                .buckets
+                .last()
+                .map_or(self.flow.period_end, |bucket| bucket.start),
+            QuotaKind::TokenBucket => now,
+        }
+    }
+
+    /// Returns the moment after which a transfer counted in `period` no longer
+    /// affects the flow, so it can't be undone
+    pub fn period_active_until(&self, period: Timestamp) -> Timestamp {
+        match self.quota.kind {
+            QuotaKind::Fixed => self.flow.period_end,
+            QuotaKind::SlidingWindow { .. } => period.plus_seconds(self.quota.duration),
+            QuotaKind::TokenBucket => self.flow.period_end,
+        }
+    }
+
+    /// Reverts a transfer that was counted in `period`, as returned by
+    /// period_of_transfer(). The value is only removed while that period is
+    /// active, so that the reverted amount doesn't make room in a later one.
+    /// For token buckets, only the part of the transfer that hasn't refilled
+    /// yet is reverted. Returns true if any value was removed.
+    pub fn undo_transfer_in(
+        &mut self,
+        direction: FlowType,
+        funds: Uint256,
+        period: Timestamp,
+        now: Timestamp,
+    ) -> bool {
+        match self.quota.kind {
+            QuotaKind::Fixed => {
+                if self.period_of_transfer(now) != period || self.flow.is_expired(now) {
+                    return false;
+                }
+                self.flow.undo_flow(direction, funds);
+                true
+            }
+            QuotaKind::SlidingWindow { .. } => {
+                let duration = self.quota.duration;
+                let bucket = self.buckets.iter_mut().find(|bucket| {
+                    bucket.start == period && bucket.start.plus_seconds(duration) >= now
+                });
+                let bucket = match bucket {
+                    Some(bucket) => bucket,
+                    None => return false,
+                };
+                let amount = match direction {
+                    FlowType::In => &mut bucket.inflow,
+                    FlowType::Out => &mut bucket.outflow,
+                };
+                let undone = cmp::min(*amount, funds);
+                *amount -= undone;
+                self.flow.undo_flow(direction, undone);
+                !undone.is_zero()
+            }
+            QuotaKind::TokenBucket => {
+                self.refill(now);
+                let elapsed = now.nanos().saturating_sub(period.nanos());
+                let duration = self.quota.duration.saturating_mul(1_000_000_000);
+                let refilled = self
+                    .quota
+                    .capacity_on(&direction)
+                    .multiply_ratio(elapsed, duration);
+                let undone = funds.saturating_sub(refilled);
+                self.flow.undo_flow(direction, undone);
+                self.flow.period_end = self.full_at(now);
+                !undone.is_zero()
+            }
+        }
+    }
+
+    /// Builds the rate limits for a set of quotas, keeping the flow of the
+    /// limits in `existing` whose quota hasn't changed. Address trackers are
+    /// updated this way when they are used, so changing the address quotas of
+    /// a path doesn't require rewriting the trackers of every address.
+    pub fn for_quotas(quotas: &[Quota], mut existing: Vec<RateLimit>, now: Timestamp) -> Vec<Self> {
+        quotas
+            .iter()
+            .map(|quota| {
+                let position = existing.iter().position(|limit| {
+                    &Quota {
+                        channel_value: None,
+                        last_price: None,
+                        ..limit.quota.clone()
+                    } == quota
+                });
+                match position {
+                    Some(position) => existing.swap_remove(position),
+                    None => RateLimit::new(quota.clone(), now),
+                }
+            })
+            .collect()
+    }
+
+    /// Converts an amount of the denom of the path into the unit the flow of
+    /// the quota is measured in, using the last price of priced quotas
+    pub fn value_of(&self, funds: Uint256) -> Uint256 {
+        match (&self.quota.price, self.quota.last_price) {
+            (Some(_), Some(price)) => {
+                funds.multiply_ratio(price.atomics(), Decimal::one().atomics())
+            }
+            _ => funds,
+        }
+    }
+
+    /// Resets the flow so that tracking starts again from `now`
+    pub fn reset(&mut self, now: Timestamp) {
+        self.flow.expire(now, &self.quota);
+        self.buckets.clear();
+        self.last_refill = None;
+    }
+
     /// Checks if a transfer is allowed and updates the data structures
     /// accordingly.
     ///
@@ -269,25 +915,29 @@ impl RateLimit {
     ) -> Result<Self, ContractError> {
         // Flow used before this transaction is applied.
         // This is used to make error messages more informative
-        let initial_flow = self.flow.balance_on(direction);
+        let initial_flow = self.flow.usage_on(direction, self.quota.gross);
 
         // Apply the transfer. From here on, we will updated the flow with the new transfer
         // and check if  it exceeds the quota at the current time
 
-        let expired = self.flow.apply_transfer(direction, funds, now, &self.quota);
+        let expired = self.apply_transfer(direction, funds, now);
         // Cache the channel value if it has never been set or it has expired.
-        if self.quota.channel_value.is_none() || expired {
-            self.quota.channel_value = Some(calculate_channel_value(
-                channel_value,
-                &path.denom,
-                funds,
-                direction,
-            ))
+        // Token buckets have no periods to keep the value for, so it is
+        // refreshed on every refill
+        if self.quota.kind == QuotaKind::TokenBucket {
+            self.quota.channel_value = Some(channel_value);
+            // The refill rate depends on the channel value that was just cached
+            self.flow.period_end = self.full_at(now);
+        } else if self.quota.channel_value.is_none() || expired {
+            self.quota.channel_value = Some(channel_value);
         }
 
         let (max_in, max_out) = self.quota.capacity();
         // Return the effects of applying the transfer or an error.
-        match self.flow.exceeds(direction, max_in, max_out) {
+        match self
+            .flow
+            .exceeds(direction, max_in, max_out, self.quota.gross)
+        {
             true => Err(ContractError::RateLimitExceded {
                 channel: path.channel.to_string(),
                 denom: path.denom.to_string(),
@@ -297,10 +947,7 @@ impl RateLimit {
                 max: self.quota.capacity_on(direction),
                 reset: self.flow.period_end,
             }),
-            false => Ok(RateLimit {
-                quota: self.quota.clone(), // Cloning here because self.quota.name (String) does not allow us to implement Copy
-                flow: self.flow, // We can Copy flow, so this is slightly more efficient than cloning the whole RateLimit
-            }),
+            false => Ok(self.clone()),
         }
     }
 }
@@ -311,6 +958,48 @@ pub const GOVMODULE: Item<Addr> = Item::new("gov_module");
 /// Only this address can execute transfers. This will likely be the
 /// IBC transfer module, but could be set to something else if needed
 pub const IBCMODULE: Item<Addr> = Item::new("ibc_module");
+/// This address can pause and unpause traffic. It is meant to be a party that
+/// can react faster than governance when an exploit is detected
+pub const GUARDIAN: Item<Addr> = Item::new("guardian");
+/// Address proposed by governance to become the new GOVMODULE. It only takes
+/// over once it accepts the role, which prevents handing the contract over to
+/// an address that can't operate it.
+pub const PENDING_GOVMODULE: Item<Addr> = Item::new("pending_gov_module");
+/// Address proposed by governance to become the new IBCMODULE
+pub const PENDING_IBCMODULE: Item<Addr> = Item::new("pending_ibc_module");
+
+/// Number of seconds that changes loosening the rate limits have to wait
+/// before they can be executed. Changes are applied right away if it isn't set.
+pub const TIMELOCK: Item<u64> = Item::new("timelock");
+
+/// Whether the responses of transfers include the usage of every quota. They
+/// cost gas on every packet, so they are left out unless this is set.
+pub const VERBOSE_RESPONSES: Item<bool> = Item::new("verbose_responses");
+
+/// Contracts built with the verbose_responses feature (like the one used by
+/// the go tests) always include the usage of the quotas in their responses
+pub fn verbose_responses(storage: &dyn Storage) -> StdResult<bool> {
+    if cfg!(feature = "verbose_responses") {
+        return Ok(true);
+    }
+    Ok(VERBOSE_RESPONSES.may_load(storage)?.unwrap_or_default())
+}
+
+/// A change that loosens the rate limits and is waiting for the timelock to
+/// expire
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingChange {
+    pub id: u64,
+    /// The change is executed on behalf of the address that requested it
+    pub proposer: Addr,
+    pub msg: ExecuteMsg,
+    pub executable_at: Timestamp,
+}
+
+/// PENDING_CHANGES maps the id of each queued change to its content. Ids are
+/// never reused.
+pub const PENDING_CHANGES: Map<u64, PendingChange> = Map::new("pending_changes");
+pub const NEXT_PENDING_CHANGE_ID: Item<u64> = Item::new("next_pending_change_id");
 
 /// RATE_LIMIT_TRACKERS is the main state for this contract. It maps a path (IBC
 /// Channel + denom) to a vector of `RateLimit`s.
@@ -332,6 +1021,238 @@ pub const IBCMODULE: Item<Addr> = Item::new("ibc_module");
 /// PrimaryKey trait
 pub const RATE_LIMIT_TRACKERS: Map<(String, String), Vec<RateLimit>> = Map::new("flow");
 
+/// PATHS_BY_DENOM is a secondary index of RATE_LIMIT_TRACKERS. The map key
+/// represents (denom, channel_id) so that the paths of a denom can be listed
+/// without iterating over all the trackers. It has to be updated whenever a
+/// path is added or removed.
+pub const PATHS_BY_DENOM: Map<(String, String), Empty> = Map::new("paths_by_denom");
+
+/// CHANNEL_RATE_LIMIT_TRACKERS maps a channel_id to the rate limits that apply
+/// to all the denoms transferred through it combined. This allows capping the
+/// total value that can leave through a channel, i.e. if the counterparty
+/// chain is compromised. The flow of these rate limits is measured in weighted
+/// value (see DENOM_WEIGHTS).
+pub const CHANNEL_RATE_LIMIT_TRACKERS: Map<String, Vec<RateLimit>> = Map::new("channel_flow");
+
+/// DENOM_WEIGHTS maps a denom to the value of each unit of it for the channel
+/// rate limits, so that amounts of different denoms can be added up. Transfers
+/// of denoms without a weight are not counted by them.
+pub const DENOM_WEIGHTS: Map<String, Decimal> = Map::new("denom_weights");
+
+/// Returns the value counted by the channel rate limits for an amount of a
+/// denom, if the denom has a weight
+pub fn weighted_value(
+    storage: &dyn Storage,
+    denom: &str,
+    amount: Uint256,
+) -> StdResult<Option<Uint256>> {
+    let weight = DENOM_WEIGHTS.may_load(storage, denom.to_string())?;
+    Ok(weight.map(|weight| amount.multiply_ratio(weight.atomics(), Decimal::one().atomics())))
+}
+
+/// ADDRESS_QUOTAS maps a path (channel_id, denom) to the quotas that each
+/// address is individually subject to when transferring through it. This
+/// prevents a single account from using up the capacity of the whole path.
+/// Like path quotas, they can be set for the "any" channel.
+pub const ADDRESS_QUOTAS: Map<(String, String), Vec<Quota>> = Map::new("address_quotas");
+
+/// ADDRESS_RATE_LIMIT_TRACKERS tracks the flow of each address through a path
+/// with address quotas. The map key represents (channel_id, denom, address),
+/// where the address is the sender on sends and the receiver on receives.
+pub const ADDRESS_RATE_LIMIT_TRACKERS: Map<(String, String, String), Vec<RateLimit>> =
+    Map::new("address_flow");
+
+/// Saves the trackers of an address. Trackers that no longer count anything
+/// are the same as new ones, so they are removed instead of being kept for
+/// every address that ever transferred through the path.
+pub fn save_address_trackers(
+    storage: &mut dyn Storage,
+    key: (String, String, String),
+    limits: &[RateLimit],
+    now: Timestamp,
+) -> StdResult<()> {
+    if limits.iter().all(|limit| limit.is_unused(now)) {
+        ADDRESS_RATE_LIMIT_TRACKERS.remove(storage, key);
+        return Ok(());
+    }
+    ADDRESS_RATE_LIMIT_TRACKERS.save(storage, key, &limits.to_vec())
+}
+
+/// Removes the trackers of every address for a path, i.e.: when its address
+/// quotas are removed
+pub fn remove_address_trackers(storage: &mut dyn Storage, path: &Path) -> StdResult<()> {
+    let addresses = ADDRESS_RATE_LIMIT_TRACKERS
+        .prefix((path.channel.clone(), path.denom.clone()))
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<String>>>()?;
+    for address in addresses {
+        let key = (path.channel.clone(), path.denom.clone(), address);
+        ADDRESS_RATE_LIMIT_TRACKERS.remove(storage, key);
+    }
+    Ok(())
+}
+
+/// The period in which one of the rate limits of a path counted a send
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct SendPeriod {
+    /// The channel of the rate limit: the one of the packet or "any"
+    pub channel_id: String,
+    /// Only set for the rate limits of an address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Set for the rate limits of a whole channel (see CHANNEL_RATE_LIMIT_TRACKERS)
+    #[serde(default)]
+    pub whole_channel: bool,
+    /// The value the rate limit counted, if it isn't the amount of the send.
+    /// That is, the weighted value for channel quotas and the priced value for
+    /// quotas with a price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Uint256>,
+    pub quota_name: String,
+    /// See RateLimit::period_of_transfer()
+    pub period: Timestamp,
+}
+
+/// The periods in which a send was counted, so that undoing it only gives the
+/// capacity back to those periods
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct SentPacket {
+    pub periods: Vec<SendPeriod>,
+    /// The moment after which none of the periods is active anymore. It is
+    /// computed when the send happens, so a quota made longer afterwards can
+    /// have its record pruned early. Undoing the send then frees nothing
+    pub expires: Timestamp,
+}
+
+/// SENT_PACKETS records the sends that have been counted by the rate limits
+/// and could still be undone. The map key represents (source_channel,
+/// sequence), which identifies the packet. Records are removed when the send
+/// is undone or, if it never is, some time after they expire.
+pub const SENT_PACKETS: Map<(String, u64), SentPacket> = Map::new("sent_packets");
+
+/// SENT_PACKETS_BY_EXPIRY is a secondary index of SENT_PACKETS. The map key
+/// represents (expires, source_channel, sequence), with the expiry in
+/// nanoseconds, so that the expired records can be found in order.
+pub const SENT_PACKETS_BY_EXPIRY: Map<(u64, String, u64), Empty> =
+    Map::new("sent_packets_by_expiry");
+
+/// FIRST_RECORDED_SEQUENCE maps a source channel to the sequence of the first
+/// send through it that went through the records of SENT_PACKETS. Sends with a
+/// lower sequence (or through channels without an entry) predate the records,
+/// i.e.: they were sent before migrating from 0.1.0.
+pub const FIRST_RECORDED_SEQUENCE: Map<String, u64> = Map::new("first_recorded_sequence");
+
+/// Checks if a send happened before sends started being recorded on its
+/// channel, so the lack of a record doesn't mean that it can't be undone
+pub fn predates_send_records(
+    storage: &dyn Storage,
+    channel_id: &str,
+    sequence: u64,
+) -> StdResult<bool> {
+    let first = FIRST_RECORDED_SEQUENCE.may_load(storage, channel_id.to_string())?;
+    Ok(first.map_or(true, |first| sequence < first))
+}
+
+/// ExemptionMode determines how the transfers of an exempt address are handled
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExemptionMode {
+    /// The transfer is neither checked nor added to the flow
+    Skip,
+    /// The transfer is added to the flow, but never rejected
+    Record,
+}
+
+/// EXEMPTIONS lists the addresses that are exempt from rate limiting. The map
+/// key represents (address, channel_id, denom). Either the channel or the
+/// denom can be "any" so that the exemption applies to all of them.
+pub const EXEMPTIONS: Map<(String, String, String), ExemptionMode> = Map::new("exemptions");
+
+/// Returns the exemption that applies to an address on a path, if any. The
+/// most specific exemption is used: the one for the exact path, then the one
+/// for any channel, then the one for any denom, and lastly the global one.
+pub fn find_exemption(
+    storage: &dyn Storage,
+    address: &str,
+    path: &Path,
+) -> StdResult<Option<ExemptionMode>> {
+    let candidates = [
+        (path.channel.as_str(), path.denom.as_str()),
+        ("any", path.denom.as_str()),
+        (path.channel.as_str(), "any"),
+        ("any", "any"),
+    ];
+    for (channel, denom) in candidates {
+        let key = (address.to_string(), channel.to_string(), denom.to_string());
+        if let Some(mode) = EXEMPTIONS.may_load(storage, key)? {
+            return Ok(Some(mode));
+        }
+    }
+    Ok(None)
+}
+
+/// Where the channel value that percentage quotas are calculated on comes from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelValueSource {
+    /// The total supply of the denom
+    Supply,
+    /// The amount of the denom held in escrow for the channel. This is only
+    /// meaningful for denoms native to this chain
+    Escrow,
+    /// A value set by governance
+    Fixed { value: Uint256 },
+}
+
+impl Default for ChannelValueSource {
+    fn default() -> Self {
+        ChannelValueSource::Supply
+    }
+}
+
+/// CHANNEL_VALUE_SOURCES maps a path (channel_id, denom) to the source of its
+/// channel value. Paths that aren't in the map use the total supply. The
+/// channel can be "any" to set the source of all the paths of a denom.
+pub const CHANNEL_VALUE_SOURCES: Map<(String, String), ChannelValueSource> =
+    Map::new("channel_value_sources");
+
+/// Returns the source of the channel value of a path. The source of the exact
+/// path takes precedence over the one set for any channel.
+pub fn find_channel_value_source(
+    storage: &dyn Storage,
+    path: &Path,
+) -> StdResult<ChannelValueSource> {
+    for channel in [path.channel.as_str(), "any"] {
+        let key = (channel.to_string(), path.denom.clone());
+        if let Some(source) = CHANNEL_VALUE_SOURCES.may_load(storage, key)? {
+            return Ok(source);
+        }
+    }
+    Ok(ChannelValueSource::default())
+}
+
+/// PAUSED holds the traffic that is currently halted, mapped to the moment it
+/// was paused. The map key represents (channel_id, denom) and either can be
+/// "any" to pause a whole channel, a denom on all channels, or everything.
+pub const PAUSED: Map<(String, String), Timestamp> = Map::new("paused");
+
+/// Checks if transfers through a path have been paused by any of the scopes
+/// that include it
+pub fn is_paused(storage: &dyn Storage, path: &Path) -> bool {
+    let candidates = [
+        (path.channel.as_str(), path.denom.as_str()),
+        ("any", path.denom.as_str()),
+        (path.channel.as_str(), "any"),
+        ("any", "any"),
+    ];
+    for (channel, denom) in candidates {
+        if PAUSED.has(storage, (channel.to_string(), denom.to_string())) {
+            return true;
+        }
+    }
+    false
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -358,7 +1279,8 @@ pub mod tests {
         // Adding flow doesn't affect expiration
         assert!(!flow.is_expired(epoch.plus_seconds(RESET_TIME_DAILY)));
 
-        flow.expire(epoch.plus_seconds(RESET_TIME_WEEKLY), RESET_TIME_WEEKLY);
+        let quota = Quota::try_from(&QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10)).unwrap();
+        flow.expire(epoch.plus_seconds(RESET_TIME_WEEKLY), &quota);
         assert_eq!(flow.balance(), (0_u32.into(), 0_u32.into()));
         assert_eq!(flow.inflow, Uint256::from(0_u32));
         assert_eq!(flow.outflow, Uint256::from(0_u32));
@@ -369,4 +1291,407 @@ pub mod tests {
         assert!(!flow.is_expired(epoch.plus_seconds(RESET_TIME_WEEKLY * 2)));
         assert!(flow.is_expired(epoch.plus_seconds(RESET_TIME_WEEKLY * 2).plus_nanos(1)));
     }
+
+    #[test] // Tests that aligned periods always end at the next boundary
+    fn aligned_periods() {
+        let path = Path::new("channel", "denom");
+        let midnight = Timestamp::from_seconds(RESET_TIME_DAILY * 100);
+        let one_am = midnight.plus_seconds(3600);
+        let quota =
+            Quota::try_from(&QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10).aligned(0)).unwrap();
+
+        // The first period is shorter so that it ends at midnight
+        let mut rate_limit = RateLimit::new(quota.clone(), one_am);
+        assert_eq!(
+            rate_limit.flow.period_end,
+            midnight.plus_seconds(RESET_TIME_DAILY)
+        );
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 5_u32.into(), 100_u32.into(), one_am)
+            .unwrap();
+
+        // Transfers at the boundary still count towards the period that ends there
+        let boundary = midnight.plus_seconds(RESET_TIME_DAILY);
+        rate_limit
+            .allow_transfer(
+                &path,
+                &FlowType::Out,
+                5_u32.into(),
+                100_u32.into(),
+                boundary,
+            )
+            .unwrap();
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(10_u32));
+
+        // The next period doesn't drift with the time of the first transfer
+        let ten_am = boundary.plus_seconds(3600 * 10).plus_nanos(500);
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 5_u32.into(), 100_u32.into(), ten_am)
+            .unwrap();
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(5_u32));
+        assert_eq!(
+            rate_limit.flow.period_end,
+            midnight.plus_seconds(RESET_TIME_DAILY * 2)
+        );
+
+        // The boundaries can be shifted from the epoch
+        let quota = Quota {
+            alignment: Some(3600 * 6),
+            ..quota
+        };
+        assert_eq!(quota.period_end(one_am), midnight.plus_seconds(3600 * 6));
+        assert_eq!(
+            quota.period_end(midnight.plus_seconds(3600 * 6)),
+            midnight.plus_seconds(3600 * 30)
+        );
+
+        // Only fixed periods can be aligned
+        let msg = QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10)
+            .with_kind(QuotaKind::SlidingWindow { buckets: 4 })
+            .aligned(0);
+        assert_eq!(
+            Quota::try_from(&msg).unwrap_err(),
+            ContractError::InvalidQuotaAlignment {
+                quota_id: format!("daily")
+            }
+        );
+    }
+
+    #[test] // Tests which quota changes allow more value to be transferred
+    fn quota_loosening() {
+        let quota = |send: u32, recv: u32, duration: u64| {
+            Quota::try_from(&QuotaMsg::new("quota", duration, send, recv)).unwrap()
+        };
+        let current = quota(10, 10, RESET_TIME_WEEKLY);
+
+        assert!(!current.is_looser_than(&current));
+        assert!(!quota(5, 10, RESET_TIME_WEEKLY).is_looser_than(&current));
+        assert!(!quota(10, 10, RESET_TIME_MONTHLY).is_looser_than(&current));
+        assert!(quota(10, 11, RESET_TIME_WEEKLY).is_looser_than(&current));
+        assert!(quota(5, 5, RESET_TIME_DAILY).is_looser_than(&current));
+
+        // Removing a limit loosens the quota, adding one tightens it
+        let with_amounts = Quota {
+            max_amount_send: Some(100_u32.into()),
+            max_amount_recv: Some(100_u32.into()),
+            ..current.clone()
+        };
+        assert!(!with_amounts.is_looser_than(&current));
+        assert!(current.is_looser_than(&with_amounts));
+
+        // Quotas of a different kind can't be compared
+        let sliding = Quota {
+            kind: QuotaKind::SlidingWindow { buckets: 7 },
+            ..current.clone()
+        };
+        assert!(sliding.is_looser_than(&current));
+
+        // Adding, changing or removing the alignment can end the period early
+        let aligned = |offset: u64| Quota {
+            alignment: Some(offset),
+            ..current.clone()
+        };
+        assert!(aligned(0).is_looser_than(&current));
+        assert!(aligned(3600).is_looser_than(&aligned(0)));
+        assert!(current.is_looser_than(&aligned(0)));
+        assert!(!aligned(0).is_looser_than(&aligned(0)));
+
+        // Netting the flows loosens gross quotas
+        let gross = Quota {
+            gross: true,
+            ..current.clone()
+        };
+        assert!(!gross.is_looser_than(&current));
+        assert!(current.is_looser_than(&gross));
+
+        // Removing or renaming a quota loosens the limits of the path
+        let renamed = Quota {
+            name: format!("renamed"),
+            ..current.clone()
+        };
+        let path_quotas = vec![current.clone()];
+        assert!(!loosens_quotas(&path_quotas, &[current, renamed.clone()]));
+        assert!(loosens_quotas(&path_quotas, &[renamed]));
+        assert!(loosens_quotas(&path_quotas, &[]));
+    }
+
+    #[test] // Tests that gross quotas don't let inflows make room for more outflows
+    fn gross_flows() {
+        let epoch = Timestamp::from_seconds(0);
+        let path = Path::new("channel", "denom");
+        let msg = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10);
+        let netted = RateLimit::new(Quota::try_from(&msg).unwrap(), epoch);
+        let gross = RateLimit::new(Quota::try_from(&msg.gross()).unwrap(), epoch);
+
+        // The channel value of 1000 gives us a capacity of 100
+        for (mut rate_limit, allowed) in [(netted, true), (gross, false)] {
+            let mut transfer = |direction: FlowType, amount: u32| {
+                rate_limit.allow_transfer(&path, &direction, amount.into(), 1000_u32.into(), epoch)
+            };
+            transfer(FlowType::Out, 80).unwrap();
+            transfer(FlowType::In, 80).unwrap();
+            assert_eq!(transfer(FlowType::Out, 30).is_ok(), allowed);
+        }
+
+        let msg = QuotaMsg::new("weekly", RESET_TIME_WEEKLY, 10, 10).gross();
+        let mut rate_limit = RateLimit::new(Quota::try_from(&msg).unwrap(), epoch);
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 80_u32.into(), 1000_u32.into(), epoch)
+            .unwrap();
+        rate_limit
+            .allow_transfer(&path, &FlowType::In, 100_u32.into(), 1000_u32.into(), epoch)
+            .unwrap();
+        assert_eq!(
+            rate_limit.flow.usage(true),
+            (Uint256::from(100_u32), Uint256::from(80_u32))
+        );
+        assert_eq!(
+            rate_limit
+                .flow
+                .remaining_on(&FlowType::Out, 100_u32.into(), true),
+            Uint256::from(20_u32)
+        );
+    }
+
+    #[test] // Tests that sliding window quotas only forget the buckets that have left the window
+    fn sliding_window() {
+        let epoch = Timestamp::from_seconds(0);
+        let path = Path::new("channel", "denom");
+        let quota = Quota {
+            name: "daily".to_string(),
+            max_percentage_send: Some(Decimal::from_ratio(10_u32, 1_u32)),
+            max_percentage_recv: Some(Decimal::from_ratio(10_u32, 1_u32)),
+            max_amount_send: None,
+            max_amount_recv: None,
+            duration: RESET_TIME_DAILY,
+            channel_value: None,
+            kind: QuotaKind::SlidingWindow { buckets: 4 },
+            alignment: None,
+            gross: false,
+            price: None,
+            last_price: None,
+        };
+        let mut rate_limit = RateLimit::new(quota, epoch);
+        let six_hours = RESET_TIME_DAILY / 4;
+
+        // The channel value of 100 gives us a capacity of 10
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 5_u32.into(), 100_u32.into(), epoch)
+            .unwrap();
+        rate_limit
+            .allow_transfer(
+                &path,
+                &FlowType::Out,
+                3_u32.into(),
+                100_u32.into(),
+                epoch.plus_seconds(six_hours - 1),
+            )
+            .unwrap();
+        // Both transfers happen in the first bucket
+        assert_eq!(rate_limit.buckets.len(), 1);
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(8_u32));
+        assert_eq!(
+            rate_limit.flow.period_end,
+            epoch.plus_seconds(RESET_TIME_DAILY)
+        );
+
+        // A new bucket is started after six hours
+        rate_limit
+            .allow_transfer(
+                &path,
+                &FlowType::Out,
+                2_u32.into(),
+                100_u32.into(),
+                epoch.plus_seconds(six_hours),
+            )
+            .unwrap();
+        assert_eq!(rate_limit.buckets.len(), 2);
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(10_u32));
+
+        // The rate limit is unused only once every bucket has left the window
+        let window_end = |start: u64| epoch.plus_seconds(start + RESET_TIME_DAILY).plus_nanos(1);
+        assert!(!rate_limit.is_unused(window_end(0)));
+        assert!(rate_limit.is_unused(window_end(six_hours)));
+
+        // Once the first bucket leaves the window, only its value is freed
+        let now = epoch.plus_seconds(RESET_TIME_DAILY).plus_nanos(1);
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 8_u32.into(), 100_u32.into(), now)
+            .unwrap();
+        assert_eq!(rate_limit.buckets.len(), 2);
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(10_u32));
+        assert_eq!(
+            rate_limit.flow.period_end,
+            epoch.plus_seconds(six_hours + RESET_TIME_DAILY)
+        );
+        let err = rate_limit
+            .allow_transfer(&path, &FlowType::Out, 1_u32.into(), 100_u32.into(), now)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceded { .. }));
+
+        // allow_transfer() applied the failed transfer to the rate limit (the
+        // contract discards it on errors). Undoing removes the value from the
+        // newest buckets first
+        rate_limit.undo_transfer(FlowType::Out, 10_u32.into());
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(1_u32));
+        assert_eq!(rate_limit.buckets[0].outflow, Uint256::from(1_u32));
+        assert_eq!(rate_limit.buckets[1].outflow, Uint256::from(0_u32));
+
+        // Resetting clears the window
+        rate_limit.reset(now);
+        assert!(rate_limit.buckets.is_empty());
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(0_u32));
+    }
+
+    #[test] // Tests that token bucket quotas refill linearly instead of resetting at once
+    fn token_bucket() {
+        let epoch = Timestamp::from_seconds(0);
+        let path = Path::new("channel", "denom");
+        let msg =
+            QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10).with_kind(QuotaKind::TokenBucket);
+        let mut rate_limit = RateLimit::new(Quota::try_from(&msg).unwrap(), epoch);
+        let six_hours = RESET_TIME_DAILY / 4;
+
+        // The channel value of 1000 gives us a capacity of 100
+        let send = |rate_limit: &mut RateLimit, amount: u32, now: Timestamp| {
+            rate_limit
+                .allow_transfer(&path, &FlowType::Out, amount.into(), 1000_u32.into(), now)
+                .map(|limit| (limit.flow.outflow, limit.flow.period_end))
+        };
+        assert_eq!(
+            send(&mut rate_limit, 100, epoch).unwrap(),
+            (Uint256::from(100_u32), epoch.plus_seconds(RESET_TIME_DAILY))
+        );
+
+        // A quarter of the capacity is available again after a quarter of the duration
+        let now = epoch.plus_seconds(six_hours);
+        assert!(send(&mut rate_limit.clone(), 26, now).is_err());
+        let now = now.plus_seconds(six_hours);
+        assert_eq!(
+            send(&mut rate_limit, 50, now).unwrap(),
+            (Uint256::from(100_u32), now.plus_seconds(RESET_TIME_DAILY))
+        );
+
+        // Frequent transfers don't stop the capacity from refilling, even if
+        // each of them happens before a whole unit has been refilled
+        let mut now = now;
+        for _ in 0..(six_hours / 60) {
+            now = now.plus_seconds(60);
+            send(&mut rate_limit, 0, now).unwrap();
+        }
+        assert_eq!(
+            send(&mut rate_limit, 0, now).unwrap(),
+            (
+                Uint256::from(75_u32),
+                now.plus_seconds(RESET_TIME_DAILY * 3 / 4)
+            )
+        );
+
+        // Inflows free up capacity right away, as flows are netted
+        rate_limit
+            .allow_transfer(&path, &FlowType::In, 25_u32.into(), 1000_u32.into(), now)
+            .unwrap();
+        assert_eq!(
+            rate_limit.flow.usage_on(&FlowType::Out, false),
+            Uint256::from(50_u32)
+        );
+
+        // Once the bucket is full it doesn't keep refilling
+        let limit = rate_limit.at(now.plus_seconds(RESET_TIME_WEEKLY));
+        assert_eq!(limit.flow.balance(), (Uint256::zero(), Uint256::zero()));
+        assert_eq!(limit.flow.period_end, now.plus_seconds(RESET_TIME_WEEKLY));
+
+        // The channel value is refreshed on every refill, even if the bucket
+        // isn't full. Doubling it doubles the capacity and the refill rate
+        let now = now.plus_seconds(six_hours);
+        rate_limit
+            .allow_transfer(&path, &FlowType::Out, 100_u32.into(), 2000_u32.into(), now)
+            .unwrap();
+        assert_eq!(rate_limit.quota.channel_value, Some(2000_u32.into()));
+        assert_eq!(rate_limit.flow.outflow, Uint256::from(125_u32));
+        assert_eq!(
+            rate_limit.flow.period_end,
+            now.plus_seconds(RESET_TIME_DAILY * 125 / 200)
+        );
+    }
+
+    #[test] // Tests that each direction of a token bucket refills at its own rate
+    fn token_bucket_uneven_refill() {
+        let epoch = Timestamp::from_seconds(0);
+        let path = Path::new("channel", "denom");
+        let msg = QuotaMsg::new("daily", RESET_TIME_DAILY, 100, 1)
+            .with_kind(QuotaKind::TokenBucket)
+            .gross();
+        let mut rate_limit = RateLimit::new(Quota::try_from(&msg).unwrap(), epoch);
+
+        // The channel value of 1000 gives us a capacity of 1000 out and 10 in
+        let transfer =
+            |rate_limit: &mut RateLimit, direction: FlowType, amount: u32, seconds: u64| {
+                rate_limit
+                    .allow_transfer(
+                        &path,
+                        &direction,
+                        amount.into(),
+                        1000_u32.into(),
+                        epoch.plus_seconds(seconds),
+                    )
+                    .map(|limit| limit.flow)
+            };
+        transfer(&mut rate_limit, FlowType::In, 5, 0).unwrap();
+        transfer(&mut rate_limit, FlowType::Out, 1000, 8000).unwrap();
+
+        // The time the inflow hasn't refilled a whole unit for isn't credited
+        // to the outflow
+        assert!(transfer(&mut rate_limit.clone(), FlowType::Out, 50, 8001).is_err());
+        assert!(transfer(&mut rate_limit.clone(), FlowType::Out, 11, 8864).is_err());
+        transfer(&mut rate_limit, FlowType::Out, 10, 8864).unwrap();
+
+        // And the refills of the outflow don't delay the inflow
+        let flow = transfer(&mut rate_limit, FlowType::In, 0, RESET_TIME_DAILY / 2 - 1).unwrap();
+        assert_eq!(flow.inflow, Uint256::from(1_u32));
+        let flow = transfer(&mut rate_limit, FlowType::In, 0, RESET_TIME_DAILY / 2).unwrap();
+        assert_eq!(flow.inflow, Uint256::zero());
+    }
+
+    #[test] // Tests that sends are only undone from the period that counted them
+    fn undo_in_period() {
+        let epoch = Timestamp::from_seconds(0);
+        let path = Path::new("channel", "denom");
+        let six_hours = RESET_TIME_DAILY / 4;
+        let rate_limit = |kind: QuotaKind| {
+            let msg = QuotaMsg::new("daily", RESET_TIME_DAILY, 10, 10).with_kind(kind);
+            RateLimit::new(Quota::try_from(&msg).unwrap(), epoch)
+        };
+        // The channel value of 1000 gives us a capacity of 100
+        let send = |rate_limit: &mut RateLimit, amount: u32, now: Timestamp| {
+            rate_limit
+                .allow_transfer(&path, &FlowType::Out, amount.into(), 1000_u32.into(), now)
+                .unwrap();
+            rate_limit.period_of_transfer(now)
+        };
+
+        // Sliding windows remove the value from the bucket of the send, as long
+        // as the bucket is still part of the window
+        let mut sliding = rate_limit(QuotaKind::SlidingWindow { buckets: 4 });
+        let first = send(&mut sliding, 50, epoch);
+        let second = send(&mut sliding, 30, epoch.plus_seconds(six_hours));
+        let now = epoch.plus_seconds(six_hours);
+        assert!(sliding.undo_transfer_in(FlowType::Out, 50_u32.into(), first, now));
+        assert_eq!(sliding.flow.outflow, Uint256::from(30_u32));
+        assert_eq!(sliding.buckets[0].outflow, Uint256::zero());
+        let now = epoch.plus_seconds(six_hours + RESET_TIME_DAILY + 1);
+        assert!(!sliding.undo_transfer_in(FlowType::Out, 30_u32.into(), second, now));
+        assert_eq!(sliding.flow.outflow, Uint256::from(30_u32));
+
+        // Token buckets only revert the part of the send that hasn't refilled
+        let mut bucket = rate_limit(QuotaKind::TokenBucket);
+        let period = send(&mut bucket, 100, epoch);
+        let now = epoch.plus_seconds(six_hours);
+        assert!(bucket.undo_transfer_in(FlowType::Out, 100_u32.into(), period, now));
+        assert_eq!(bucket.flow.outflow, Uint256::zero());
+        assert_eq!(bucket.flow.period_end, now);
+        let period = send(&mut bucket, 100, now);
+        let now = now.plus_seconds(RESET_TIME_DAILY);
+        assert!(!bucket.undo_transfer_in(FlowType::Out, 100_u32.into(), period, now));
+    }
 }
@@ -1,8 +1,17 @@
-use cosmwasm_std::{DepsMut, Response, Timestamp, Uint256};
+use cosmwasm_std::{DepsMut, Empty, Order, Response, StdResult, Storage, Timestamp, Uint256};
+use cw_storage_plus::Bound;
+use std::cmp;
+use std::collections::BTreeMap;
 
 use crate::{
+    channel_value::{quota_price, value_before_transfer, ChannelValueProvider},
     packet::Packet,
-    state::{FlowType, Path, RateLimit, RATE_LIMIT_TRACKERS},
+    state::{
+        find_exemption, is_paused, predates_send_records, save_address_trackers, verbose_responses,
+        weighted_value, ExemptionMode, FlowType, Path, RateLimit, SendPeriod, SentPacket,
+        ADDRESS_QUOTAS, ADDRESS_RATE_LIMIT_TRACKERS, CHANNEL_RATE_LIMIT_TRACKERS,
+        FIRST_RECORDED_SEQUENCE, RATE_LIMIT_TRACKERS, SENT_PACKETS, SENT_PACKETS_BY_EXPIRY,
+    },
     ContractError,
 };
 
@@ -18,54 +27,231 @@ pub fn process_packet(
     packet: Packet,
     direction: FlowType,
     now: Timestamp,
-    #[cfg(test)] channel_value_mock: Option<Uint256>,
+    provider: &dyn ChannelValueProvider,
 ) -> Result<Response, ContractError> {
     let (channel_id, denom) = packet.path_data(&direction);
     let path = &Path::new(channel_id, denom);
     let funds = packet.get_funds();
+    let address = packet.local_address(&direction);
 
-    #[cfg(test)]
-    // When testing we override the channel value with the mock since we can't get it from the chain
-    let channel_value = match channel_value_mock {
-        Some(channel_value) => channel_value,
-        None => packet.channel_value(deps.as_ref(), &direction)?, // This should almost never be used, but left for completeness in case we want to send an empty channel_value from the test
-    };
+    let channel_value = value_before_transfer(deps.storage, provider, path, funds, &direction)?;
 
-    #[cfg(not(test))]
-    let channel_value = packet.channel_value(deps.as_ref(), &direction)?;
+    try_transfer(
+        deps,
+        path,
+        &address,
+        packet.sequence,
+        channel_value,
+        funds,
+        direction,
+        now,
+        provider,
+    )
+}
 
-    try_transfer(deps, path, channel_value, funds, direction, now)
+/// The rate limits that apply to a transfer, with the transfer applied to them
+#[derive(Debug, Default)]
+pub struct TransferCheck {
+    pub exemption: Option<ExemptionMode>,
+    pub trackers: Vec<RateLimit>,
+    pub any_trackers: Vec<RateLimit>,
+    /// The trackers of the address, keyed by (channel_id, denom, address)
+    pub address_trackers: Vec<((String, String, String), Vec<RateLimit>)>,
+    /// The trackers of the whole channel. They count the weighted value of the
+    /// transfer, which is only set if the denom has a weight
+    pub channel_trackers: Vec<RateLimit>,
+    pub weighted_value: Option<Uint256>,
 }
 
-/// This function checks the rate limit and, if successful, stores the updated data about the value
-/// that has been transfered through the channel for a specific denom.
+impl TransferCheck {
+    pub fn is_empty(&self) -> bool {
+        self.trackers.is_empty()
+            && self.any_trackers.is_empty()
+            && self.address_trackers.is_empty()
+            && self.channel_trackers.is_empty()
+    }
+}
+
+/// This function checks the rate limits that apply to a transfer without
+/// modifying the state. If the transfer is allowed, it returns the updated
+/// rate limits so that the caller can decide whether to store them.
 /// If the period for a RateLimit has ended, the Flow information is reset.
 ///
 /// The channel_value is the current value of the denom for the the channel as
 /// calculated by the caller. This should be the total supply of a denom
-pub fn try_transfer(
-    deps: DepsMut,
+///
+/// Quotas with a price convert the funds to their reference denom with the
+/// price obtained from the provider (see quota_price())
+///
+/// The address is the one moving the funds on this chain, if known. If the
+/// path (or the "any" channel) has address quotas, the flow of the address is
+/// also checked against them. Exempt addresses are either not rate limited at
+/// all or only have their transfers recorded, depending on the mode of the
+/// exemption.
+#[allow(clippy::too_many_arguments)]
+pub fn check_transfer(
+    storage: &dyn Storage,
     path: &Path,
+    address: Option<&str>,
     channel_value: Uint256,
     funds: Uint256,
-    direction: FlowType,
+    direction: &FlowType,
     now: Timestamp,
-) -> Result<Response, ContractError> {
-    // Sudo call. Only go modules should be allowed to access this
+    provider: &dyn ChannelValueProvider,
+) -> Result<TransferCheck, ContractError> {
+    // Paused traffic is rejected for everyone, including exempt addresses
+    if is_paused(storage, path) {
+        return Err(ContractError::Paused {
+            channel: path.channel.to_string(),
+            denom: path.denom.to_string(),
+        });
+    }
+
+    let exemption = match address {
+        Some(address) => find_exemption(storage, address, path)?,
+        None => None,
+    };
+    if exemption == Some(ExemptionMode::Skip) {
+        return Ok(TransferCheck {
+            exemption,
+            ..Default::default()
+        });
+    }
 
     // Fetch potential trackers for "any" channel of the required token
     let any_path = Path::new("any", path.denom.clone());
     let mut any_trackers = RATE_LIMIT_TRACKERS
-        .may_load(deps.storage, any_path.clone().into())?
+        .may_load(storage, any_path.clone().into())?
         .unwrap_or_default();
     // Fetch trackers for the requested path
     let mut trackers = RATE_LIMIT_TRACKERS
-        .may_load(deps.storage, path.into())?
+        .may_load(storage, path.into())?
         .unwrap_or_default();
+    // Fetch the trackers of the address for the paths that have address quotas
+    let mut address_trackers = vec![];
+    if let Some(address) = address {
+        for quota_path in [path, &any_path] {
+            let quotas = ADDRESS_QUOTAS
+                .may_load(storage, quota_path.into())?
+                .unwrap_or_default();
+            if quotas.is_empty() {
+                continue;
+            }
+            let key = (
+                quota_path.channel.clone(),
+                quota_path.denom.clone(),
+                address.to_string(),
+            );
+            let existing = ADDRESS_RATE_LIMIT_TRACKERS
+                .may_load(storage, key.clone())?
+                .unwrap_or_default();
+            address_trackers.push((key, RateLimit::for_quotas(&quotas, existing, now)));
+        }
+    }
+    // Fetch the trackers of the whole channel if the denom counts towards them
+    let weighted_value = weighted_value(storage, &path.denom, funds)?;
+    let mut channel_trackers = match weighted_value {
+        Some(_) => CHANNEL_RATE_LIMIT_TRACKERS
+            .may_load(storage, path.channel.clone())?
+            .unwrap_or_default(),
+        None => vec![],
+    };
+
+    // If any of the RateLimits fails, allow_transfer() will return
+    // ContractError::RateLimitExceded, which we'll propagate out. Exempt
+    // transfers are never rejected. allow_transfer() has already added them to
+    // the flow when it fails, so they are still recorded.
+    let allow_transfer = |limit: &mut RateLimit,
+                          path: &Path,
+                          funds: Uint256|
+     -> Result<(), ContractError> {
+        let priced = match &limit.quota.price {
+            Some(price) => quota_price(provider, price, &path.denom, limit.quota.last_price, now)
+                .map(|price| limit.quota.last_price = Some(price)),
+            None => Ok(()),
+        };
+        let result = priced.and_then(|_| {
+            let value = limit.value_of(funds);
+            limit.allow_transfer(path, direction, value, channel_value, now)
+        });
+        match result {
+            Err(_) if exemption.is_some() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    };
+
+    for limit in trackers.iter_mut().chain(any_trackers.iter_mut()) {
+        allow_transfer(limit, path, funds)?;
+    }
+
+    for ((_, _, address), limits) in address_trackers.iter_mut() {
+        for limit in limits.iter_mut() {
+            allow_transfer(limit, path, funds)
+                .map_err(|err| address_limit_exceeded(err, address))?;
+        }
+    }
+
+    // Channel quotas only limit absolute amounts, so the channel value of the
+    // denom doesn't affect them. They are reported as the "any" denom
+    let channel_path = Path::new(path.channel.clone(), "any");
+    for limit in channel_trackers.iter_mut() {
+        allow_transfer(limit, &channel_path, weighted_value.unwrap_or_default())?;
+    }
 
-    let not_configured = trackers.is_empty() && any_trackers.is_empty();
+    Ok(TransferCheck {
+        exemption,
+        trackers,
+        any_trackers,
+        address_trackers,
+        channel_trackers,
+        weighted_value,
+    })
+}
 
-    if not_configured {
+/// This function checks the rate limit and, if successful, stores the updated data about the value
+/// that has been transfered through the channel for a specific denom.
+///
+/// Sends are recorded by their sequence so that they can be undone later.
+#[allow(clippy::too_many_arguments)]
+pub fn try_transfer(
+    deps: DepsMut,
+    path: &Path,
+    address: &str,
+    sequence: u64,
+    channel_value: Uint256,
+    funds: Uint256,
+    direction: FlowType,
+    now: Timestamp,
+    provider: &dyn ChannelValueProvider,
+) -> Result<Response, ContractError> {
+    // Sudo call. Only go modules should be allowed to access this
+    let check = check_transfer(
+        deps.storage,
+        path,
+        Some(address),
+        channel_value,
+        funds,
+        &direction,
+        now,
+        provider,
+    )?;
+
+    // Every send from now on is recorded if it counts towards any quota
+    if direction == FlowType::Out
+        && !FIRST_RECORDED_SEQUENCE.has(deps.storage, path.channel.clone())
+    {
+        FIRST_RECORDED_SEQUENCE.save(deps.storage, path.channel.clone(), &sequence)?;
+    }
+
+    if check.exemption == Some(ExemptionMode::Skip) {
+        return Ok(Response::new()
+            .add_attribute("method", "try_transfer")
+            .add_attribute("channel_id", path.channel.to_string())
+            .add_attribute("denom", path.denom.to_string())
+            .add_attribute("exemption", "skip"));
+    }
+
+    if check.is_empty() {
         // No Quota configured for the current path. Allowing all messages.
         return Ok(Response::new()
             .add_attribute("method", "try_transfer")
@@ -74,43 +260,88 @@ pub fn try_transfer(
             .add_attribute("quota", "none"));
     }
 
-    // If any of the RateLimits fails, allow_transfer() will return
-    // ContractError::RateLimitExceded, which we'll propagate out
-    let results: Vec<RateLimit> = trackers
-        .iter_mut()
-        .map(|limit| limit.allow_transfer(path, &direction, funds, channel_value, now))
-        .collect::<Result<_, ContractError>>()?;
-
-    let any_results: Vec<RateLimit> = any_trackers
-        .iter_mut()
-        .map(|limit| limit.allow_transfer(path, &direction, funds, channel_value, now))
-        .collect::<Result<_, ContractError>>()?;
-
-    RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &results)?;
-    RATE_LIMIT_TRACKERS.save(deps.storage, any_path.into(), &any_results)?;
+    // Only the paths with quotas are stored. Otherwise, every path of a denom
+    // with quotas on another channel would get an empty entry
+    let any_path = Path::new("any", path.denom.clone());
+    if !check.trackers.is_empty() {
+        RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &check.trackers)?;
+    }
+    if !check.any_trackers.is_empty() {
+        RATE_LIMIT_TRACKERS.save(deps.storage, any_path.into(), &check.any_trackers)?;
+    }
+    for (key, limits) in &check.address_trackers {
+        save_address_trackers(deps.storage, key.clone(), limits, now)?;
+    }
+    if !check.channel_trackers.is_empty() {
+        CHANNEL_RATE_LIMIT_TRACKERS.save(
+            deps.storage,
+            path.channel.clone(),
+            &check.channel_trackers,
+        )?;
+    }
+    if direction == FlowType::Out {
+        prune_sent_packets(deps.storage, now)?;
+        record_send(deps.storage, path, sequence, funds, &check, now)?;
+    }
 
-    let response = Response::new()
+    let mut response = Response::new()
         .add_attribute("method", "try_transfer")
         .add_attribute("channel_id", path.channel.to_string())
         .add_attribute("denom", path.denom.to_string());
+    if !check.address_trackers.is_empty() {
+        response = response.add_attribute("address", address);
+    }
+    if check.exemption.is_some() {
+        response = response.add_attribute("exemption", "record");
+    }
 
-    // Adds the attributes for each path to the response. In prod, the
-    // addtribute add_rate_limit_attributes is a noop
-    let response: Result<Response, ContractError> =
-        any_results.iter().fold(Ok(response), |acc, result| {
-            Ok(add_rate_limit_attributes(acc?, result))
-        });
-    results.iter().fold(Ok(response?), |acc, result| {
-        Ok(add_rate_limit_attributes(acc?, result))
-    })
+    // Adds the attributes for each path to the response. In prod, they are
+    // left out unless governance turns them on
+    if !verbose_responses(deps.storage)? {
+        return Ok(response);
+    }
+    let response = check
+        .any_trackers
+        .iter()
+        .chain(check.trackers.iter())
+        .fold(response, add_rate_limit_attributes);
+    Ok(check
+        .channel_trackers
+        .iter()
+        .fold(response, add_rate_limit_attributes))
+}
+
+// Address quotas are checked with the same logic as path quotas. This makes
+// the error say which address ran out of capacity.
+fn address_limit_exceeded(err: ContractError, address: &str) -> ContractError {
+    match err {
+        ContractError::RateLimitExceded {
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        } => ContractError::AddressRateLimitExceded {
+            address: address.to_string(),
+            channel,
+            denom,
+            amount,
+            quota_name,
+            used,
+            max,
+            reset,
+        },
+        err => err,
+    }
 }
 
-// #[cfg(any(feature = "verbose_responses", test))]
+// Only called when verbose_responses() is set. That way we avoid calculating
+// these on every packet on prod.
 fn add_rate_limit_attributes(response: Response, result: &RateLimit) -> Response {
-    let (used_in, used_out) = result.flow.balance();
+    let (used_in, used_out) = result.flow.usage(result.quota.gross);
     let (max_in, max_out) = result.quota.capacity();
-    // These attributes are only added during testing. That way we avoid
-    // calculating these again on prod.
     response
         .add_attribute(
             format!("{}_used_in", result.quota.name),
@@ -131,63 +362,219 @@ fn add_rate_limit_attributes(response: Response, result: &RateLimit) -> Response
         )
 }
 
-// Leaving the attributes in until we can conditionally compile the contract
-// for the go tests in CI: https://github.com/mandrean/cw-optimizoor/issues/19
+// Removes a send without a record from the current period of the quotas of the
+// path. 0.1.0 only had path quotas, so no address or channel quota counted it.
+fn undo_unrecorded_send(
+    deps: DepsMut,
+    path: &Path,
+    funds: Uint256,
+    response: Response,
+) -> Result<Response, ContractError> {
+    let mut any_channel = false;
+    for channel_id in [path.channel.as_str(), "any"] {
+        let key = (channel_id.to_string(), path.denom.clone());
+        let mut limits = match RATE_LIMIT_TRACKERS.may_load(deps.storage, key.clone())? {
+            Some(limits) => limits,
+            None => continue,
+        };
+        for limit in limits.iter_mut() {
+            limit.undo_transfer(FlowType::Out, funds);
+        }
+        any_channel |= channel_id == "any";
+        RATE_LIMIT_TRACKERS.save(deps.storage, key, &limits)?;
+    }
+    Ok(response
+        .add_attribute("recorded", "false")
+        .add_attribute("any_channel", any_channel.to_string()))
+}
+
+/// The maximum number of expired send records removed on each send. This keeps
+/// the cost of the cleanup bounded.
+const MAX_PRUNED_SENT_PACKETS: usize = 10;
+
+// Stores the period in which each rate limit counted a send. Skipped sends and
+// sends through paths without quotas are not recorded.
+fn record_send(
+    storage: &mut dyn Storage,
+    path: &Path,
+    sequence: u64,
+    funds: Uint256,
+    check: &TransferCheck,
+    now: Timestamp,
+) -> StdResult<()> {
+    let limits = check
+        .trackers
+        .iter()
+        .map(|limit| (path.channel.as_str(), None, false, limit))
+        .chain(
+            check
+                .any_trackers
+                .iter()
+                .map(|limit| ("any", None, false, limit)),
+        )
+        .chain(
+            check
+                .address_trackers
+                .iter()
+                .flat_map(|((channel_id, _, address), limits)| {
+                    limits
+                        .iter()
+                        .map(move |limit| (channel_id.as_str(), Some(address), false, limit))
+                }),
+        )
+        .chain(
+            check
+                .channel_trackers
+                .iter()
+                .map(|limit| (path.channel.as_str(), None, true, limit)),
+        );
+
+    let mut periods = vec![];
+    let mut expires = now;
+    for (channel_id, address, whole_channel, limit) in limits {
+        let period = limit.period_of_transfer(now);
+        expires = cmp::max(expires, limit.period_active_until(period));
+        // The price of a quota is the one of its last transfer, which is this one
+        let value = match whole_channel {
+            true => check.weighted_value,
+            false => limit.quota.price.as_ref().map(|_| limit.value_of(funds)),
+        };
+        periods.push(SendPeriod {
+            channel_id: channel_id.to_string(),
+            address: address.cloned(),
+            whole_channel,
+            value,
+            quota_name: limit.quota.name.clone(),
+            period,
+        });
+    }
+
+    let key = (path.channel.clone(), sequence);
+    if let Some(previous) = SENT_PACKETS.may_load(storage, key.clone())? {
+        SENT_PACKETS_BY_EXPIRY.remove(storage, (previous.expires.nanos(), key.0.clone(), key.1));
+    }
+    SENT_PACKETS.save(storage, key.clone(), &SentPacket { periods, expires })?;
+    SENT_PACKETS_BY_EXPIRY.save(storage, (expires.nanos(), key.0, key.1), &Empty {})
+}
+
+// Removes the records of sends that can no longer be undone. Sends that
+// succeed are never undone, so their records would otherwise stay forever.
+fn prune_sent_packets(storage: &mut dyn Storage, now: Timestamp) -> StdResult<()> {
+    let expired = SENT_PACKETS_BY_EXPIRY
+        .keys(
+            storage,
+            None,
+            Some(Bound::exclusive((now.nanos(), String::new(), 0))),
+            Order::Ascending,
+        )
+        .take(MAX_PRUNED_SENT_PACKETS)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (expires, channel_id, sequence) in expired {
+        SENT_PACKETS_BY_EXPIRY.remove(storage, (expires, channel_id.clone(), sequence));
+        SENT_PACKETS.remove(storage, (channel_id, sequence));
+    }
+    Ok(())
+}
+
+// This function removes a send from the flow. This is used when reverting a
+// packet that failed ack or timed-out. The send is only removed from the
+// periods that counted it and are still active. Otherwise, reverting it would
+// free up capacity in a period that never included it.
 //
-// #[cfg(not(any(feature = "verbose_responses", test)))]
-// fn add_rate_limit_attributes(response: Response, _result: &RateLimit) -> Response {
-//     response
-// }
-
-// This function manually injects an inflow. This is used when reverting a
-// packet that failed ack or timed-out.
-pub fn undo_send(deps: DepsMut, packet: Packet) -> Result<Response, ContractError> {
+// Sends from before the records (see predates_send_records()) are removed
+// from the current period of the path quotas instead, like 0.1.0 did.
+pub fn undo_send(deps: DepsMut, packet: Packet, now: Timestamp) -> Result<Response, ContractError> {
     // Sudo call. Only go modules should be allowed to access this
     let (channel_id, denom) = packet.path_data(&FlowType::Out); // Sends have direction out.
     let path = &Path::new(channel_id, &denom);
-    let any_path = Path::new("any", &denom);
     let funds = packet.get_funds();
+    let response = Response::new()
+        .add_attribute("method", "undo_send")
+        .add_attribute("channel_id", path.channel.to_string())
+        .add_attribute("denom", path.denom.to_string());
 
-    let mut any_trackers = RATE_LIMIT_TRACKERS
-        .may_load(deps.storage, any_path.clone().into())?
-        .unwrap_or_default();
-    let mut trackers = RATE_LIMIT_TRACKERS
-        .may_load(deps.storage, path.into())?
-        .unwrap_or_default();
+    let key = (path.channel.clone(), packet.sequence);
+    let sent = match SENT_PACKETS.may_load(deps.storage, key.clone())? {
+        Some(sent) => sent,
+        None if predates_send_records(deps.storage, &path.channel, packet.sequence)? => {
+            return undo_unrecorded_send(deps, path, funds, response);
+        }
+        // The send wasn't counted by any quota, its periods have ended or it
+        // was already undone. Nothing to revert
+        None => return Ok(response.add_attribute("quota", "none")),
+    };
+    SENT_PACKETS.remove(deps.storage, key.clone());
+    SENT_PACKETS_BY_EXPIRY.remove(deps.storage, (sent.expires.nanos(), key.0, key.1));
 
-    let not_configured = trackers.is_empty() && any_trackers.is_empty();
+    // The rate limits are stored in a list for each path (and address, or the
+    // whole channel), so the periods are grouped by the list they belong to
+    type ListKey = (String, Option<String>, bool);
+    let mut by_list: BTreeMap<ListKey, Vec<&SendPeriod>> = BTreeMap::new();
+    for period in &sent.periods {
+        let key = (
+            period.channel_id.clone(),
+            period.address.clone(),
+            period.whole_channel,
+        );
+        by_list.entry(key).or_default().push(period);
+    }
 
-    if not_configured {
-        // No Quota configured for the current path. Allowing all messages.
-        return Ok(Response::new()
-            .add_attribute("method", "try_transfer")
-            .add_attribute("channel_id", path.channel.to_string())
-            .add_attribute("denom", path.denom.to_string())
-            .add_attribute("quota", "none"));
+    let mut any_channel = false;
+    let mut address_undone = None;
+    let mut channel_undone = false;
+    for ((channel_id, address, whole_channel), periods) in by_list {
+        let path_key = (channel_id.clone(), denom.clone());
+        let limits = match &address {
+            _ if whole_channel => {
+                CHANNEL_RATE_LIMIT_TRACKERS.may_load(deps.storage, channel_id.clone())?
+            }
+            Some(address) => ADDRESS_RATE_LIMIT_TRACKERS.may_load(
+                deps.storage,
+                (channel_id.clone(), denom.clone(), address.clone()),
+            )?,
+            None => RATE_LIMIT_TRACKERS.may_load(deps.storage, path_key.clone())?,
+        };
+        // The path (or the quotas of the address) may have been removed since
+        let mut limits = match limits {
+            Some(limits) => limits,
+            None => continue,
+        };
+        for period in periods {
+            if let Some(limit) = limits
+                .iter_mut()
+                .find(|limit| limit.quota.name == period.quota_name)
+            {
+                // Channel and priced quotas counted a value other than the funds
+                let amount = period.value.unwrap_or(funds);
+                limit.undo_transfer_in(FlowType::Out, amount, period.period, now);
+            }
+        }
+        match address {
+            _ if whole_channel => {
+                CHANNEL_RATE_LIMIT_TRACKERS.save(deps.storage, channel_id, &limits)?;
+                channel_undone = true;
+            }
+            Some(address) => {
+                save_address_trackers(
+                    deps.storage,
+                    (channel_id, denom.clone(), address.clone()),
+                    &limits,
+                    now,
+                )?;
+                address_undone = Some(address);
+            }
+            None => {
+                any_channel |= channel_id == "any";
+                RATE_LIMIT_TRACKERS.save(deps.storage, path_key, &limits)?;
+            }
+        }
     }
 
-    // We force update the flow to remove a failed send
-    let results: Vec<RateLimit> = trackers
-        .iter_mut()
-        .map(|limit| {
-            limit.flow.undo_flow(FlowType::Out, funds);
-            limit.to_owned()
-        })
-        .collect();
-    let any_results: Vec<RateLimit> = any_trackers
-        .iter_mut()
-        .map(|limit| {
-            limit.flow.undo_flow(FlowType::Out, funds);
-            limit.to_owned()
-        })
-        .collect();
-
-    RATE_LIMIT_TRACKERS.save(deps.storage, path.into(), &results)?;
-    RATE_LIMIT_TRACKERS.save(deps.storage, any_path.into(), &any_results)?;
-
-    Ok(Response::new()
-        .add_attribute("method", "undo_send")
-        .add_attribute("channel_id", path.channel.to_string())
-        .add_attribute("denom", path.denom.to_string())
-        .add_attribute("any_channel", (!any_trackers.is_empty()).to_string()))
+    let response = response
+        .add_attribute("any_channel", any_channel.to_string())
+        .add_attribute("whole_channel", channel_undone.to_string());
+    match address_undone {
+        Some(address) => Ok(response.add_attribute("address", address)),
+        None => Ok(response),
+    }
 }